@@ -0,0 +1,68 @@
+//! Converts a recording of CW (e.g. sample/*.flac) to the mark/space
+//! timings read by `decode --timings` (see sample/timings):
+//!
+//! ```text
+//! cargo run --example flac_timings -- sample/paddle-sample.flac [MERGE_GAPS_MS]
+//! ```
+//!
+//! The tone's envelope is taken in 5 ms windows, and keyed above half
+//! of its 99th percentile. Gaps shorter than `MERGE_GAPS_MS` (e.g. the
+//! tone fading on an indirect recording) are merged into the marks.
+use rodio::Source;
+use std::fs::File;
+use std::io::BufReader;
+
+const WINDOW_MS: u32 = 5;
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let path = args
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("usage: flac_timings FILE [MERGE_GAPS_MS]"))?;
+    let merge_gaps: u32 = args.next().map(|ms| ms.parse()).transpose()?.unwrap_or(0);
+
+    let decoder = rodio::Decoder::new(BufReader::new(File::open(&path)?))?;
+    let channels = usize::from(decoder.channels());
+    let window = (decoder.sample_rate() * WINDOW_MS / 1000) as usize * channels;
+    let samples: Vec<i16> = decoder.collect();
+    let envelope: Vec<f32> = samples
+        .chunks(window)
+        .map(|chunk| {
+            // The peak of the channels' mean:
+            chunk
+                .chunks(channels)
+                .map(|frame| frame.iter().map(|&s| f32::from(s)).sum::<f32>() / channels as f32)
+                .fold(0., |peak, s| f32::max(peak, s.abs()))
+        })
+        .collect();
+    let mut sorted = envelope.clone();
+    sorted.sort_by(f32::total_cmp);
+    let threshold = sorted[sorted.len() * 99 / 100] / 2.;
+
+    let mut runs: Vec<(bool, u32)> = Vec::new();
+    for mark in envelope
+        .iter()
+        .map(|&e| e > threshold)
+        .skip_while(|mark| !mark)
+    {
+        match runs.last_mut() {
+            Some((last, ms)) if *last == mark => *ms += WINDOW_MS,
+            _ => runs.push((mark, WINDOW_MS)),
+        }
+    }
+    // Merge the short gaps (and the marks around them):
+    let mut merged: Vec<(bool, u32)> = Vec::new();
+    for (mark, ms) in runs {
+        match merged.last_mut() {
+            Some((true, last)) if mark => *last += ms,
+            Some((true, last)) if ms < merge_gaps => *last += ms,
+            _ => merged.push((mark, ms)),
+        }
+    }
+
+    println!("state,ms");
+    for (mark, ms) in merged {
+        println!("{},{ms}", if mark { "mark" } else { "space" });
+    }
+    Ok(())
+}
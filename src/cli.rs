@@ -11,7 +11,7 @@ pub fn app() -> Command {
                 .global(true)
                 .num_args(1)
                 .value_name("DOT_DURATION")
-                .value_parser(value_parser!(u32))
+                .value_parser(value_parser!(u32).range(1..))
                 .help("Sets the dot duration in milliseconds [default: 60]"),
         )
         .arg(
@@ -20,7 +20,7 @@ pub fn app() -> Command {
                 .global(true)
                 .num_args(1)
                 .value_name("WPM")
-                .value_parser(value_parser!(u32))
+                .value_parser(value_parser!(u32).range(1..))
                 .help(
                     "Sets the speed in words per minute [default: 20]",
                 ),
@@ -54,14 +54,157 @@ pub fn app() -> Command {
                     "Output sound in addition to the --text option",
                 ),
         )
+        .arg(
+            Arg::new("output-device")
+                .long("output-device")
+                .global(true)
+                .num_args(1)
+                .value_name("NAME")
+                .help("Play sound on this audio output device (see `test-sound --list-devices`)"),
+        )
+        .arg(
+            Arg::new("pipewire-node")
+                .long("pipewire-node")
+                .global(true)
+                .num_args(0..=1)
+                .value_name("NAME")
+                .default_missing_value("code-smore CW")
+                .conflicts_with("output-device")
+                .help("Play sound into a PipeWire source node that other applications can record from [default name: code-smore CW]"),
+        )
+        .arg(
+            Arg::new("volume")
+                .long("volume")
+                .global(true)
+                .num_args(1)
+                .value_name("VOLUME")
+                .default_value("1.0")
+                .value_parser(|v: &str| {
+                    v.parse::<f32>()
+                        .map_err(|_| String::from("Volume must be a valid floating-point number"))
+                        .and_then(|val| {
+                            if (0.0..=1.0).contains(&val) {
+                                Ok(val)
+                            } else {
+                                Err(String::from("Volume must be between 0.0 and 1.0"))
+                            }
+                        })
+                })
+                .help("Sets the sound volume [0.0..1.0]"),
+        )
         .arg(
             Arg::new("rts")
                 .long("rts")
                 .global(true)
                 .num_args(1)
                 .value_name("PORT")
+                .help("Assert RTS on this serial port while transmitting (e.g. /dev/ttyUSB0)")
+        )
+        .arg(
+            Arg::new("gpio-buzzer")
+                .long("gpio-buzzer")
+                .global(true)
+                .num_args(1)
+                .value_name("PIN")
+                .value_parser(value_parser!(u8))
+                .conflicts_with_all(["output-device", "pipewire-node"])
+                .help("Sound the tone on a piezo buzzer on this GPIO pin (PWM) instead of the sound device, and as a sidetone when keying GPIO"),
+        )
+        .arg(
+            Arg::new("gpio-ptt")
+                .long("gpio-ptt")
+                .global(true)
+                .num_args(1)
+                .value_name("PIN")
+                .value_parser(value_parser!(u8))
+                .conflicts_with_all(["rts", "rigctld"])
+                .help("Assert this GPIO output pin while transmitting, to switch the transmitter"),
+        )
+        .arg(
+            Arg::new("ptt-lead")
+                .long("ptt-lead")
+                .global(true)
+                .num_args(1)
+                .value_name("MS")
+                .value_parser(value_parser!(u64))
+                .default_value("0")
+                .help("Wait this long after asserting PTT before sending"),
+        )
+        .arg(
+            Arg::new("ptt-tail")
+                .long("ptt-tail")
+                .global(true)
+                .num_args(1)
+                .value_name("MS")
+                .value_parser(value_parser!(u64))
+                .default_value("0")
+                .help("Keep PTT asserted this long after the last element"),
+        )
+        .arg(
+            Arg::new("ptt-hang")
+                .long("ptt-hang")
+                .global(true)
+                .num_args(1)
+                .value_name("MS")
+                .value_parser(value_parser!(u64))
+                .default_value("0")
+                .help("Semi-break-in hang time: keep PTT asserted across gaps shorter than this"),
+        )
+        .arg(
+            Arg::new("key-line")
+                .long("key-line")
+                .global(true)
+                .num_args(1)
+                .value_name("LINE")
+                .value_parser(["dtr", "rts"])
                 .conflicts_with("gpio")
-                .help("Assert RTS on this serial port while playing sound (e.g. /dev/ttyUSB0)")
+                .help("Key CW on this line of the serial port (see --key-port) instead of playing sound"),
+        )
+        .arg(
+            Arg::new("key-port")
+                .long("key-port")
+                .global(true)
+                .num_args(1)
+                .value_name("PORT")
+                .requires("key-line")
+                .help("Serial port for --key-line [default: the --rts port]"),
+        )
+        .arg(
+            Arg::new("midi-out")
+                .long("midi-out")
+                .global(true)
+                .num_args(1)
+                .value_name("PORT")
+                .conflicts_with_all(["gpio", "key-line"])
+                .help("Key CW as notes (at the --tone pitch) on this MIDI output port, by name or number, instead of playing sound"),
+        )
+        .arg(
+            Arg::new("rigctld")
+                .long("rigctld")
+                .global(true)
+                .num_args(1)
+                .value_name("HOST:PORT")
+                .conflicts_with("rts")
+                .help("Switch PTT through Hamlib rigctld (e.g. localhost:4532) instead of RTS, and log the rig frequency when receiving"),
+        )
+        .arg(
+            Arg::new("rig-keyer")
+                .long("rig-keyer")
+                .global(true)
+                .action(clap::ArgAction::SetTrue)
+                .requires("rigctld")
+                .conflicts_with_all(["gpio", "key-line"])
+                .help("Send text with the rig's internal keyer (through --rigctld) instead of playing sound"),
+        )
+        .arg(
+            Arg::new("max-key-down")
+                .long("max-key-down")
+                .global(true)
+                .num_args(1)
+                .value_name("MS")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("10000")
+                .help("Safety watchdog: force the key up if it stays down longer than this"),
         )
         .arg(
             Arg::new("gpio")
@@ -73,6 +216,81 @@ pub fn app() -> Command {
                     "Use GPIO instead of the sound device (select GPIO pin number)",
                 ),
         )
+        .arg(
+            Arg::new("gpio-chip")
+                .long("gpio-chip")
+                .global(true)
+                .num_args(1)
+                .value_name("CHIP")
+                .help("Use the lines of this GPIO character device (e.g. gpiochip0) instead of the Raspberry Pi's GPIO"),
+        )
+        .arg(
+            Arg::new("gpio-pull")
+                .long("gpio-pull")
+                .global(true)
+                .num_args(1)
+                .value_name("PULL")
+                .value_parser(["none", "up", "down"])
+                .default_value("none")
+                .help("Internal pull resistor of the GPIO key and paddle input pins"),
+        )
+        .arg(
+            Arg::new("gpio-input-active")
+                .long("gpio-input-active")
+                .global(true)
+                .num_args(1)
+                .value_name("LEVEL")
+                .value_parser(["low", "high"])
+                .default_value("low")
+                .help("Level of a GPIO input pin while its key or paddle contact is closed"),
+        )
+        .arg(
+            Arg::new("gpio-debounce")
+                .long("gpio-debounce")
+                .global(true)
+                .num_args(1)
+                .value_name("MS")
+                .value_parser(value_parser!(u64))
+                .default_value("0")
+                .help("Ignore GPIO input changes shorter than this (contact bounce)"),
+        )
+        .arg(
+            Arg::new("gpio-output-active")
+                .long("gpio-output-active")
+                .global(true)
+                .num_args(1)
+                .value_name("LEVEL")
+                .value_parser(["high", "low"])
+                .default_value("high")
+                .help("Level of a GPIO output pin while the key is down (low for opto-isolated keying circuits)"),
+        )
+        .arg(
+            Arg::new("keyer-mode")
+                .long("keyer-mode")
+                .global(true)
+                .num_args(1)
+                .value_name("MODE")
+                .value_parser(["iambic-a", "iambic-b", "ultimatic", "bug"])
+                .default_value("iambic-b")
+                .help("How the paddle keyer responds to a squeeze"),
+        )
+        .arg(
+            Arg::new("keyer-weight")
+                .long("keyer-weight")
+                .global(true)
+                .num_args(1)
+                .value_name("PERCENT")
+                .value_parser(value_parser!(u32).range(25..=75))
+                .default_value("50")
+                .help("Paddle keyer weight: the key down share of each element [25..75]"),
+        )
+        .arg(
+            Arg::new("no-keyer-memory")
+                .long("no-keyer-memory")
+                .global(true)
+                .action(clap::ArgAction::SetTrue)
+                .help("Don't remember the other paddle tapped during an element (Iambic A and Ultimatic)"),
+        )
         .arg(
             Arg::new("log")
                 .long("log")
@@ -122,6 +340,13 @@ pub fn app() -> Command {
                         .default_value("500")
                         .value_parser(value_parser!(u32)),
                 )
+                .arg(
+                    Arg::new("output-latency")
+                        .long("output-latency")
+                        .help("The audio output latency in milliseconds (see `test-sound --loopback`)")
+                        .default_value("0")
+                        .value_parser(value_parser!(u32)),
+                )
                 .arg(
                     Arg::new("trials")
                         .long("trials")
@@ -138,9 +363,25 @@ pub fn app() -> Command {
                         .action(clap::ArgAction::SetTrue),
                 ),
         )
-        .subcommand(Command::new("test-sound").about(
-            "Test that sound is working",
-        ))
+        .subcommand(
+            Command::new("test-sound")
+                .about(
+                    "Test that sound is working",
+                )
+                .arg(
+                    Arg::new("list-devices")
+                        .long("list-devices")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("List the available audio output devices"),
+                )
+                .arg(
+                    Arg::new("loopback")
+                        .long("loopback")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("list-devices")
+                        .help("Play a known pattern while capturing the desktop audio monitor, then report latency, pitch and decode accuracy"),
+                ),
+        )
         .subcommand(
             Command::new("send")
                 .about(
@@ -153,6 +394,78 @@ pub fn app() -> Command {
                         .help(
                             "Input text is already morse encoded",
                         ),
+                )
+                .arg(
+                    Arg::new("midi-file")
+                        .long("midi-file")
+                        .value_name("PATH")
+                        .help("Also write the sent CW as notes (at the --tone pitch) to this Standard MIDI File"),
+                )
+                .arg(
+                    Arg::new("winkeyer")
+                        .long("winkeyer")
+                        .num_args(1)
+                        .value_name("PORT")
+                        .conflicts_with_all(["gpio", "key-line", "rig-keyer"])
+                        .help("Send text with a K1EL WinKeyer on this serial port (e.g. /dev/ttyUSB1) instead of playing sound"),
+                ),
+        )
+        .subcommand(
+            Command::new("mix")
+                .about(
+                    "Mix several morse code signals with different pitch, speed and pan",
+                )
+                .arg(
+                    Arg::new("station")
+                        .short('s')
+                        .long("station")
+                        .value_name("SPEC")
+                        .required(true)
+                        .action(clap::ArgAction::Append)
+                        .help(
+                            "Add a station to the mix: [tone=HZ,wpm=WPM,dot=MS,amp=0..1,offset=MS,pan=-1..1:]TEXT (may be repeated)",
+                        ),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write the mix to a WAV file instead of playing it"),
+                ),
+        )
+        .subcommand(
+            Command::new("cwdaemon")
+                .about(
+                    "Run a cwdaemon compatible UDP server, for sending CW from loggers (tlf, xlog, ...)",
+                )
+                .arg(
+                    Arg::new("port")
+                        .short('p')
+                        .long("port")
+                        .value_name("PORT")
+                        .value_parser(value_parser!(u16))
+                        .default_value("6789")
+                        .help("UDP port to listen on"),
+                )
+                .arg(
+                    Arg::new("address")
+                        .long("address")
+                        .value_name("ADDRESS")
+                        .default_value("127.0.0.1")
+                        .help("Address to listen on (use 0.0.0.0 to accept requests from other hosts)"),
+                ),
+        )
+        .subcommand(
+            Command::new("winkeyer-emulator")
+                .about(
+                    "Emulate a WinKeyer on a pseudo terminal, for sending CW from loggers (N1MM, fldigi, ...)",
+                )
+                .arg(
+                    Arg::new("link")
+                        .long("link")
+                        .value_name("PATH")
+                        .help("Create a symlink to the pseudo terminal at this path (e.g. /tmp/winkeyer)"),
                 ),
         )
         .subcommand(
@@ -206,26 +519,154 @@ pub fn app() -> Command {
                             "Minimal signal value threshold [0.0..1.0]",
                         ),
                 )
+                .args(key_input_args())
                 .arg(
                     Arg::new("listen")
                         .long("listen")
                         .help("Use PipeWire to receive morse code from default system audio monitor")
                         .action(clap::ArgAction::SetTrue)
-                        .conflicts_with_all(["device", "file"]),
+                        .conflicts_with_all(["device", "file", "gpio-dit", "input-device", "midi-in"]),
                 )
                 .arg(
                     Arg::new("file")
                         .short('f')
                         .long("file")
                         .help("Receive morse code from an audio file")
-                        .conflicts_with_all(["device", "listen"]),
+                        .conflicts_with_all(["device", "listen", "gpio-dit", "input-device", "midi-in"]),
                 )
                 .arg(
                     Arg::new("device")
                         .short('d')
                         .long("device")
                         .help("Receive morse code from an audio device")
-                        .conflicts_with_all(["file", "listen"]),
+                        .conflicts_with_all(["file", "listen", "gpio-dit", "input-device", "midi-in"]),
+                )
+                .arg(
+                    Arg::new("record")
+                        .long("record")
+                        .value_name("FILE")
+                        .help("Record the key-down/key-up timing to this .cwlog file (see `replay`)"),
+                )
+                .arg(
+                    Arg::new("analyze")
+                        .long("analyze")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Report on the timing of your fist (dits, dahs, spacing, speed and habits) after each message")
+                        .conflicts_with_all(["listen", "file", "device"]),
+                ),
+        )
+        .subcommand(
+            Command::new("practice-key")
+                .about(
+                    "Key CW from the computer keyboard (straight key or paddles) with sidetone and live decoding",
+                )
+                .arg(
+                    Arg::new("morse")
+                        .long("morse")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Output text in morse code"),
+                )
+                .arg(
+                    Arg::new("record")
+                        .long("record")
+                        .value_name("FILE")
+                        .help("Record the key-down/key-up timing to this .cwlog file (see `replay`)"),
+                )
+                .arg(
+                    Arg::new("analyze")
+                        .long("analyze")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Report on the timing of your fist (dits, dahs, spacing, speed and habits) after each message"),
+                ),
+        )
+        .subcommand(
+            Command::new("send-practice")
+                .about(
+                    "Sending trainer: key the words, callsigns or sentences shown, and get graded on accuracy and timing",
+                )
+                .arg(
+                    Arg::new("targets")
+                        .long("targets")
+                        .value_name("KIND")
+                        .value_parser(["words", "callsigns", "sentences", "mixed"])
+                        .default_value("mixed")
+                        .help("What to key"),
+                )
+                .arg(
+                    Arg::new("target")
+                        .long("target")
+                        .value_name("TEXT")
+                        .action(clap::ArgAction::Append)
+                        .help("Key this text instead (repeat for more, in order)"),
+                )
+                .arg(
+                    Arg::new("trials")
+                        .long("trials")
+                        .value_name("COUNT")
+                        .value_parser(value_parser!(u32).range(1..))
+                        .default_value("10")
+                        .help("How many targets to key"),
+                )
+                .args(key_input_args()),
+        )
+        .subcommand(
+            Command::new("replay")
+                .about(
+                    "Replay a .cwlog recording through the decoder, and out to the sound device, GPIO or serial key line",
+                )
+                .arg(
+                    Arg::new("file")
+                        .value_name("FILE")
+                        .required(true)
+                        .help("The .cwlog file recorded with `receive --record`"),
+                )
+                .arg(
+                    Arg::new("speed")
+                        .long("speed")
+                        .value_name("FACTOR")
+                        .value_parser(|v: &str| match v.parse::<f32>() {
+                            Ok(speed) if speed > 0. => Ok(speed),
+                            _ => Err(String::from("Speed must be a number greater than 0")),
+                        })
+                        .default_value("1.0")
+                        .help("Replay this many times as fast as recorded (e.g. 0.5 for half speed)"),
+                )
+                .arg(
+                    Arg::new("instant")
+                        .long("instant")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Decode the whole recording at once, without keying any output"),
+                )
+                .arg(
+                    Arg::new("analyze")
+                        .long("analyze")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Report on the timing of the recorded fist (dits, dahs, spacing, speed and habits) after each message"),
+                )
+                .arg(
+                    Arg::new("morse")
+                        .long("morse")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Output text in morse code"),
+                ),
+        )
+        .subcommand(
+            Command::new("decode")
+                .about(
+                    "Decode key timings offline (CSV of mark/space durations, or a .cwlog recording), with the confidence of each character",
+                )
+                .arg(
+                    Arg::new("timings")
+                        .long("timings")
+                        .value_name("FILE")
+                        .required(true)
+                        .help("CSV of `mark,<ms>` and `space,<ms>` lines, or a .cwlog recording"),
+                )
+                .arg(
+                    Arg::new("morse")
+                        .long("morse")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Output text in morse code"),
                 ),
         )
         .subcommand(
@@ -249,3 +690,77 @@ pub fn app() -> Command {
                 )
         )
 }
+
+/// The straight keys and paddles to key from, besides `--gpio` and the
+/// keyboard (`receive` and `send-practice`)
+fn key_input_args() -> Vec<Arg> {
+    vec![
+        Arg::new("gpio-dit")
+            .long("gpio-dit")
+            .value_name("PIN")
+            .value_parser(value_parser!(u8))
+            .requires("gpio-dah")
+            .conflicts_with("gpio")
+            .help("Key from an iambic paddle: GPIO pin of the dit contact (see --keyer-mode)"),
+        Arg::new("gpio-dah")
+            .long("gpio-dah")
+            .value_name("PIN")
+            .value_parser(value_parser!(u8))
+            .requires("gpio-dit")
+            .help("GPIO pin of the paddle's dah contact"),
+        Arg::new("gpio-key")
+            .long("gpio-key")
+            .value_name("PIN")
+            .value_parser(value_parser!(u8))
+            .requires("gpio-dit")
+            .help("Key this GPIO output pin from the paddle"),
+        Arg::new("input-device")
+            .long("input-device")
+            .value_name("PATH")
+            .conflicts_with_all(["gpio", "gpio-dit"])
+            .help("Key from a Linux input device, e.g. a USB paddle adapter (/dev/input/by-id/...); its Ctrl keys are the paddles unless --input-key or --input-dit are given"),
+        Arg::new("input-key")
+            .long("input-key")
+            .value_name("CODE")
+            .requires("input-device")
+            .help("Key code of the straight key on the input device (e.g. KEY_SPACE, see `evtest`)"),
+        Arg::new("input-dit")
+            .long("input-dit")
+            .value_name("CODE")
+            .requires_all(["input-device", "input-dah"])
+            .help("Key code of the paddle's dit contact on the input device (e.g. KEY_LEFTCTRL)"),
+        Arg::new("input-dah")
+            .long("input-dah")
+            .value_name("CODE")
+            .requires("input-dit")
+            .help("Key code of the paddle's dah contact on the input device (e.g. KEY_RIGHTCTRL)"),
+        Arg::new("input-grab")
+            .long("input-grab")
+            .action(clap::ArgAction::SetTrue)
+            .requires("input-device")
+            .help("Take the input device for code-smore alone, so its keys don't also reach other programs"),
+        Arg::new("midi-in")
+            .long("midi-in")
+            .value_name("PORT")
+            .conflicts_with_all(["gpio", "gpio-dit", "input-device"])
+            .help("Key from the notes of a MIDI keyer adapter on this input port, by name or number"),
+        Arg::new("midi-key")
+            .long("midi-key")
+            .value_name("NOTE")
+            .value_parser(value_parser!(u8).range(0..=127))
+            .default_value("0")
+            .help("MIDI note of the straight key"),
+        Arg::new("midi-dit")
+            .long("midi-dit")
+            .value_name("NOTE")
+            .value_parser(value_parser!(u8).range(0..=127))
+            .default_value("1")
+            .help("MIDI note of the paddle's dit contact"),
+        Arg::new("midi-dah")
+            .long("midi-dah")
+            .value_name("NOTE")
+            .value_parser(value_parser!(u8).range(0..=127))
+            .default_value("2")
+            .help("MIDI note of the paddle's dah contact"),
+    ]
+}
@@ -0,0 +1,263 @@
+use crate::key::Key;
+use crate::morse::{wpm_to_dot_length, CwOutput, MorsePlayer};
+use crate::prelude::*;
+use crate::ptt::PttHold;
+use anyhow::{bail, Context};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+
+/// Speed range accepted by cwdaemon (in WPM)
+const SPEED_RANGE: std::ops::RangeInclusive<u32> = 4..=60;
+
+/// A cwdaemon request: either text to send, or an escape (ESC)
+/// character followed by a command code and an optional value.
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    Text(String),
+    Reset,                     // ESC 0
+    Speed(u32),                // ESC 2 <wpm>
+    Tone(u32),                 // ESC 3 <Hz>, 0 turns the sound off
+    Abort,                     // ESC 4
+    Exit,                      // ESC 5
+    Ptt(bool),                 // ESC a <0|1>
+    Reply(String),             // ESC h <text>, echoed once the next text is sent
+    Unsupported(char, String), // Any other command
+}
+
+impl Request {
+    pub fn parse(datagram: &[u8]) -> anyhow::Result<Self> {
+        let request = String::from_utf8_lossy(datagram);
+        let request = request.trim_end_matches(['\0', '\r', '\n']);
+        let Some(command) = request.strip_prefix('\x1b') else {
+            return Ok(Request::Text(request.to_string()));
+        };
+        let mut chars = command.chars();
+        let Some(code) = chars.next() else {
+            bail!("empty escape request");
+        };
+        let value = chars.as_str().trim();
+        let number = || {
+            value
+                .parse::<u32>()
+                .with_context(|| format!("invalid value `{value}` for request ESC {code}"))
+        };
+        Ok(match code {
+            '0' => Request::Reset,
+            '2' => match number()? {
+                wpm if SPEED_RANGE.contains(&wpm) => Request::Speed(wpm),
+                wpm => bail!("speed {wpm} WPM is out of range"),
+            },
+            '3' => Request::Tone(number()?),
+            '4' => Request::Abort,
+            '5' => Request::Exit,
+            'a' => Request::Ptt(number()? != 0),
+            'h' => Request::Reply(value.to_string()),
+            code => Request::Unsupported(code, value.to_string()),
+        })
+    }
+}
+
+/// Receives requests and forwards them to the sender. Aborts are
+/// flagged right away, so the text being sent stops without waiting
+/// for its turn in the queue.
+fn receive_requests(
+    socket: UdpSocket,
+    requests: mpsc::Sender<(Request, SocketAddr)>,
+    abort: Arc<AtomicBool>,
+) {
+    let mut buffer = [0; 512];
+    loop {
+        let (length, from) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) => {
+                error!("cwdaemon socket error: {e}");
+                continue;
+            }
+        };
+        let request = match Request::parse(&buffer[..length]) {
+            Ok(request) => request,
+            Err(e) => {
+                warn!("Ignoring cwdaemon request from {from}: {e:#}");
+                continue;
+            }
+        };
+        debug!("cwdaemon request from {from}: {request:?}");
+        if matches!(request, Request::Abort | Request::Exit) {
+            abort.store(true, Ordering::SeqCst);
+        }
+        if requests.send((request, from)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Stands in for the sound while the tone is set to 0, so the text is
+/// still timed (and can be aborted) without making any sound.
+struct Mute;
+
+impl Key for Mute {
+    fn set(&mut self, _down: bool) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Serves cwdaemon requests on `address:port` until a client sends
+/// the exit request.
+pub fn run(
+    address: &str,
+    port: u16,
+    player: &MorsePlayer,
+    output: CwOutput,
+    dot_duration: u32,
+    tone_freq: f32,
+) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind((address, port))
+        .with_context(|| format!("binding cwdaemon UDP socket on {address}:{port}"))?;
+    info!("cwdaemon listening on {address}:{port} - Press Ctrl-C to stop.");
+    serve(socket, player, output, dot_duration, tone_freq)
+}
+
+fn serve(
+    socket: UdpSocket,
+    player: &MorsePlayer,
+    mut output: CwOutput,
+    dot_duration: u32,
+    tone_freq: f32,
+) -> anyhow::Result<()> {
+    let abort = Arc::new(AtomicBool::new(false));
+    let (sender, requests) = mpsc::channel();
+    {
+        let socket = socket.try_clone()?;
+        let abort = abort.clone();
+        std::thread::spawn(move || receive_requests(socket, sender, abort));
+    }
+
+    let mut speed = dot_duration;
+    let mut tone = tone_freq;
+    let mut muted = false;
+    let mut ptt: Option<PttHold> = None;
+    let mut reply: Option<(SocketAddr, String)> = None;
+    for (request, from) in requests {
+        match request {
+            Request::Text(text) => {
+                // Skip whatever was queued before an abort:
+                if abort.load(Ordering::SeqCst) {
+                    continue;
+                }
+                match &mut output {
+                    CwOutput::Sound if muted => {
+                        player.key_abortable(&text, speed, &mut Mute, &abort)
+                    }
+                    output => player.send_abortable(&text, speed, tone, output, &abort),
+                }
+                if let Some((to, text)) = reply.take() {
+                    socket.send_to(format!("h{text}\r\n").as_bytes(), to)?;
+                }
+            }
+            Request::Reset => {
+                speed = dot_duration;
+                tone = tone_freq;
+                muted = false;
+                ptt = None;
+                reply = None;
+            }
+            Request::Speed(wpm) => speed = wpm_to_dot_length(wpm),
+            Request::Tone(0) => muted = true,
+            Request::Tone(frequency) => {
+                tone = frequency as f32;
+                muted = false;
+            }
+            Request::Abort => abort.store(false, Ordering::SeqCst),
+            Request::Exit => {
+                info!("cwdaemon exit requested by {from}");
+                break;
+            }
+            Request::Ptt(true) => {
+                if ptt.is_none() {
+                    // Keep serving, the next request may get through:
+                    match player.transmit() {
+                        Ok(hold) => ptt = hold,
+                        Err(e) => error!("{e:#}"),
+                    }
+                }
+            }
+            Request::Ptt(false) => ptt = None,
+            Request::Reply(text) => reply = Some((from, text)),
+            Request::Unsupported(code, value) => {
+                debug!("Ignoring unsupported cwdaemon request ESC {code} {value}")
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_requests() {
+        let parse = |datagram: &[u8]| Request::parse(datagram).unwrap();
+        assert_eq!(parse(b"CQ TEST\r\n"), Request::Text("CQ TEST".to_string()));
+        assert_eq!(parse(b"\x1b0"), Request::Reset);
+        assert_eq!(parse(b"\x1b228"), Request::Speed(28));
+        assert_eq!(parse(b"\x1b3600\0"), Request::Tone(600));
+        assert_eq!(parse(b"\x1b30"), Request::Tone(0));
+        assert_eq!(parse(b"\x1b4"), Request::Abort);
+        assert_eq!(parse(b"\x1b5"), Request::Exit);
+        assert_eq!(parse(b"\x1ba1"), Request::Ptt(true));
+        assert_eq!(parse(b"\x1ba0"), Request::Ptt(false));
+        assert_eq!(parse(b"\x1bhDONE"), Request::Reply("DONE".to_string()));
+        assert_eq!(
+            parse(b"\x1bd20"),
+            Request::Unsupported('d', "20".to_string())
+        );
+        assert!(Request::parse(b"\x1b2fast").is_err());
+        assert!(Request::parse(b"\x1b2100").is_err());
+    }
+
+    #[test]
+    fn test_udp_session() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let player = MorsePlayer::silent(1.0);
+            serve(socket, &player, CwOutput::Sound, 60, 600.0)
+        });
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(std::time::Duration::from_secs(10)))
+            .unwrap();
+        let request = |datagram: &[u8]| client.send_to(datagram, address).unwrap();
+        let reply = || {
+            let mut buffer = [0; 64];
+            let (length, _) = client.recv_from(&mut buffer).unwrap();
+            String::from_utf8_lossy(&buffer[..length]).to_string()
+        };
+
+        // With the tone off, the text is still timed at 60 WPM (dot 20ms):
+        request(b"\x1b30");
+        request(b"\x1b260");
+        request(b"\x1bhFIRST");
+        let start = std::time::Instant::now();
+        request(b"EE");
+        assert_eq!(reply(), "hFIRST\r\n");
+        let elapsed = start.elapsed();
+        assert!(elapsed.as_millis() >= 150, "EE took {elapsed:?}");
+
+        // An abort stops the text (about 3s long) after the current character:
+        request(b"\x1bhSECOND");
+        let start = std::time::Instant::now();
+        request(b"PARIS PARIS PARIS");
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        request(b"\x1b4");
+        assert_eq!(reply(), "hSECOND\r\n");
+        let elapsed = start.elapsed();
+        assert!(elapsed.as_millis() < 1000, "abort took {elapsed:?}");
+
+        request(b"\x1b5");
+        server.join().unwrap().unwrap();
+    }
+}
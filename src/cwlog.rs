@@ -0,0 +1,294 @@
+//! Recordings of keying in the `.cwlog` format: JSON lines, a header
+//! (the source and the configured speed) followed by one line per edge
+//! of the key, e.g.
+//!
+//! ```text
+//! {"cwlog":1,"source":"GPIO pin 17","started":"2025-01-01T12:00:00+00:00","dot_ms":60,"wpm":20}
+//! {"ms":0.0,"down":true}
+//! {"ms":61.2,"down":false}
+//! ```
+//!
+//! Replaying a recording pushes its edges through the decoder again,
+//! or keys any output with them.
+use crate::decode::DecodedKey;
+use crate::morse::dot_length_to_wpm;
+use crate::prelude::*;
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const VERSION: u32 = 1;
+
+/// The first line of a recording
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Header {
+    /// The format version
+    pub cwlog: u32,
+    /// What was keyed, e.g. `GPIO pin 17` or `audio`
+    pub source: String,
+    /// When the recording started (RFC 3339)
+    pub started: String,
+    pub dot_ms: u32,
+    pub wpm: u32,
+}
+
+/// The key going down or up, at milliseconds from the start
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Edge {
+    pub ms: f64,
+    pub down: bool,
+}
+
+impl Edge {
+    pub fn at(&self) -> Duration {
+        Duration::from_secs_f64(self.ms.max(0.) / 1000.)
+    }
+}
+
+/// Writes the edges of a key to a recording as they happen
+pub struct Recorder {
+    out: LineWriter<File>,
+    last: Duration,
+}
+
+impl Recorder {
+    pub fn create(path: &str, source: &str, dot_duration: u32) -> anyhow::Result<Self> {
+        let file = File::create(path).with_context(|| format!("creating recording {path}"))?;
+        let mut recorder = Self {
+            // Written line by line, so nothing is lost when stopped:
+            out: LineWriter::new(file),
+            last: Duration::ZERO,
+        };
+        let header = Header {
+            cwlog: VERSION,
+            source: source.to_string(),
+            started: chrono::Local::now().to_rfc3339(),
+            dot_ms: dot_duration,
+            wpm: dot_length_to_wpm(dot_duration),
+        };
+        recorder
+            .write_line(&header)
+            .with_context(|| format!("writing recording {path}"))?;
+        Ok(recorder)
+    }
+
+    fn write_line<T: Serialize>(&mut self, value: &T) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, value)?;
+        self.out.write_all(b"\n")
+    }
+
+    /// The key went `down` (or up) at `at` from the start.
+    pub fn edge(&mut self, at: Duration, down: bool) -> io::Result<()> {
+        self.last = at;
+        // Kept to µs, so the lines stay short:
+        let ms = at.as_micros() as f64 / 1000.;
+        self.write_line(&Edge { ms, down })
+    }
+
+    /// The key went `down` (or up) `duration` after the previous edge.
+    pub fn edge_after(&mut self, duration: Duration, down: bool) -> io::Result<()> {
+        self.edge(self.last + duration, down)
+    }
+
+    /// Writes the edges from a thread of its own, for callers that
+    /// mustn't wait for the file (the audio callback).
+    #[cfg_attr(not(feature = "pipewire"), allow(dead_code))]
+    pub fn spawn(mut self) -> RecorderThread {
+        let (sender, edges) = mpsc::channel::<(Duration, bool)>();
+        let writer = std::thread::spawn(move || {
+            for (at, down) in edges {
+                if let Err(e) = self.edge(at, down) {
+                    warn!("Recording stopped: {e}");
+                    return;
+                }
+            }
+        });
+        RecorderThread {
+            edges: Some(sender),
+            writer: Some(writer),
+        }
+    }
+}
+
+/// A recorder writing from its own thread (see `Recorder::spawn`). The
+/// edges sent are all written when dropped.
+#[cfg_attr(not(feature = "pipewire"), allow(dead_code))]
+pub struct RecorderThread {
+    edges: Option<mpsc::Sender<(Duration, bool)>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+#[cfg_attr(not(feature = "pipewire"), allow(dead_code))]
+impl RecorderThread {
+    /// The key went `down` (or up) at `at` from the start. Returns false
+    /// once recording stopped (after a write error).
+    pub fn edge(&self, at: Duration, down: bool) -> bool {
+        self.edges
+            .as_ref()
+            .is_some_and(|edges| edges.send((at, down)).is_ok())
+    }
+}
+
+impl Drop for RecorderThread {
+    fn drop(&mut self) {
+        // Closing the channel ends the writer once it wrote everything:
+        self.edges = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Parses a recording.
+pub fn parse(reader: impl BufRead) -> anyhow::Result<(Header, Vec<Edge>)> {
+    let mut lines = reader.lines().enumerate();
+    let header: Header = match lines.next() {
+        Some((_, line)) => serde_json::from_str(&line?).context("line 1 is not a cwlog header")?,
+        None => bail!("the recording is empty"),
+    };
+    if header.cwlog > VERSION {
+        bail!("cwlog version {} is not supported", header.cwlog);
+    }
+    let mut edges = Vec::new();
+    for (i, line) in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let edge = serde_json::from_str(&line).with_context(|| format!("line {}", i + 1))?;
+        edges.push(edge);
+    }
+    Ok((header, edges))
+}
+
+/// Reads a recording file.
+pub fn read(path: &str) -> anyhow::Result<(Header, Vec<Edge>)> {
+    let file = File::open(path).with_context(|| format!("opening recording {path}"))?;
+    parse(BufReader::new(file)).with_context(|| format!("reading recording {path}"))
+}
+
+/// Replays edges `speed` times as fast as recorded, keying and decoding
+/// them as they come, or decoding them all at once when `instant`. The
+/// decoder gets the recorded timing whatever the speed, so it decodes
+/// the same. Returns the decoded messages.
+pub fn replay(
+    edges: &[Edge],
+    speed: f32,
+    mut keying: DecodedKey,
+    dot_duration: u32,
+    instant: bool,
+) -> anyhow::Result<Vec<String>> {
+    let dot = Duration::from_millis(dot_duration.into()).div_f32(speed);
+    let start = Instant::now();
+    let mut last = Duration::ZERO;
+    let mut messages = Vec::new();
+    for edge in edges {
+        let at = edge.at();
+        if !instant {
+            // Wait for the edge, finishing the message on long pauses:
+            let replayed_at = at.div_f32(speed);
+            while start.elapsed() < replayed_at {
+                std::thread::sleep(replayed_at.saturating_sub(start.elapsed()).min(dot));
+                messages.extend(keying.idle(start.elapsed().mul_f32(speed).min(at))?);
+            }
+        }
+        messages.extend(keying.idle(at)?);
+        keying.set(at, edge.down)?;
+        last = at;
+    }
+    keying.set(last, false)?;
+    // Finish the last message:
+    let message = keying
+        .idle(Duration::MAX)
+        .context("finishing the message")?;
+    messages.extend(message);
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::LiveDecoder;
+    use crate::key::Key;
+    use crate::morse::encode_morse;
+
+    #[test]
+    fn test_record_and_parse() {
+        let path = std::env::temp_dir().join(format!("code-smore-{}.cwlog", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut recorder = Recorder::create(path, "test", 60).unwrap();
+        recorder.edge(Duration::from_micros(1500), true).unwrap();
+        recorder
+            .edge_after(Duration::from_millis(60), false)
+            .unwrap();
+        drop(recorder);
+
+        let (header, edges) = read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(header.source, "test");
+        assert_eq!((header.dot_ms, header.wpm), (60, 20));
+        assert_eq!(
+            edges,
+            [
+                Edge {
+                    ms: 1.5,
+                    down: true
+                },
+                Edge {
+                    ms: 61.5,
+                    down: false
+                }
+            ]
+        );
+        assert!(parse("{\"ms\":0,\"down\":true}\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_recorder_thread() {
+        let path =
+            std::env::temp_dir().join(format!("code-smore-thread-{}.cwlog", std::process::id()));
+        let path = path.to_str().unwrap();
+        let recorder = Recorder::create(path, "test", 60).unwrap().spawn();
+        for i in 0..100 {
+            assert!(recorder.edge(Duration::from_millis(i * 60), i % 2 == 0));
+        }
+        drop(recorder); // Waits for every edge to be written
+
+        let (_, edges) = read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(edges.len(), 100);
+        assert_eq!(edges[99].ms, 99. * 60.);
+    }
+
+    #[test]
+    fn test_replay_decodes_recording() {
+        let path =
+            std::env::temp_dir().join(format!("code-smore-replay-{}.cwlog", std::process::id()));
+        let path = path.to_str().unwrap();
+        // Record "PARIS" keyed at 60ms dots:
+        let mut recorder = Recorder::create(path, "test", 60).unwrap();
+        let (mut at, mut down) = (Duration::ZERO, false);
+        for (freq, ms) in encode_morse("PARIS", 60, 440.) {
+            if (freq > 0.) != down {
+                down = !down;
+                recorder.edge(at, down).unwrap();
+            }
+            at += Duration::from_millis(ms.into());
+        }
+        drop(recorder);
+
+        let (header, edges) = read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        // Decoded the same all at once and in (sped up) real time:
+        for instant in [true, false] {
+            let decoder = LiveDecoder::new(header.dot_ms, false, None);
+            let keying = DecodedKey::new(Box::new(Vec::<Box<dyn Key>>::new()), decoder);
+            let messages = replay(&edges, 10., keying, header.dot_ms, instant).unwrap();
+            assert_eq!(messages, ["PARIS"], "instant: {instant}");
+        }
+    }
+}
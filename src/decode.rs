@@ -0,0 +1,168 @@
+use crate::cwlog::Recorder;
+use crate::fist::FistAnalyzer;
+use crate::key::Key;
+use crate::morse::{get_decoder, text_to_morse, CwDecoder};
+use crate::prelude::*;
+use crate::rigctld::Rigctld;
+use crate::send_practice::SendTrainer;
+use crate::term::print_lines;
+use std::io::Write;
+use std::time::Duration;
+
+/// Decodes key down/up durations as they happen, printing the message
+/// on the current line until a long pause finishes it.
+pub struct LiveDecoder {
+    dot_duration: u32,
+    output_morse: bool,
+    rig: Option<Rigctld>, // Tags each message with the rig frequency and mode
+    decoder: CwDecoder,
+    pending: bool, // Tracks if there's a pending message to finalize
+    recorder: Option<Recorder>,
+    analyzer: Option<FistAnalyzer>,
+    trainer: Option<SendTrainer>,
+}
+
+impl LiveDecoder {
+    pub fn new(dot_duration: u32, output_morse: bool, rig: Option<Rigctld>) -> Self {
+        Self {
+            dot_duration,
+            output_morse,
+            rig,
+            decoder: get_decoder(dot_duration),
+            pending: false,
+            recorder: None,
+            analyzer: None,
+            trainer: None,
+        }
+    }
+
+    /// Also records the key's edges (see `--record`).
+    pub fn record(mut self, recorder: Option<Recorder>) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Also reports on the fist of each message (see `--analyze`).
+    pub fn analyze(mut self, analyze: bool) -> Self {
+        self.analyzer = analyze.then(|| FistAnalyzer::new(self.dot_duration));
+        self
+    }
+
+    /// Grades each message against the trainer's targets instead (see
+    /// `send-practice`).
+    pub fn practice(mut self, trainer: Option<SendTrainer>) -> Self {
+        self.trainer = trainer;
+        self
+    }
+
+    fn print_message(&self, message: &str) -> io::Result<()> {
+        if self.output_morse {
+            print!("\r\x1b[K{}", text_to_morse(message));
+        } else {
+            print!("\r\x1b[K{message}");
+        }
+        io::stdout().flush()
+    }
+
+    /// The key was down (or up) for `duration`, and has just changed.
+    pub fn signal(&mut self, duration: Duration, was_down: bool) -> io::Result<()> {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.edge_after(duration, !was_down)?;
+        }
+        if let Some(analyzer) = self.analyzer.as_mut() {
+            analyzer.add(duration, was_down);
+        }
+        if let Some(trainer) = self.trainer.as_mut() {
+            trainer.signal(duration, was_down);
+        }
+        let duration = duration.as_millis().min(u16::MAX.into()) as u16;
+        debug!(
+            "Key {} for {duration} ms",
+            if was_down { "down" } else { "up" }
+        );
+        self.decoder.signal_event(duration, was_down);
+        self.pending = true;
+
+        // Print the current message on the same line
+        let message = self.decoder.message().trim().to_string();
+        if !message.is_empty() {
+            self.print_message(&message)?;
+        }
+        Ok(())
+    }
+
+    /// The key hasn't changed for `idle`. Finishes the message once
+    /// the pause is much longer than a word gap, and returns it.
+    pub fn idle(&mut self, idle: Duration) -> io::Result<Option<String>> {
+        if let Some(trainer) = self.trainer.as_mut() {
+            trainer.prompt();
+        }
+        if !self.pending || idle <= Duration::from_millis(6 * 7 * self.dot_duration as u64) {
+            return Ok(None);
+        }
+        self.decoder.signal_event_end(false);
+        let message = self.decoder.message().trim().to_string();
+        if !message.is_empty() {
+            debug!("Inactivity detected. Final message: {:?}", message);
+            self.print_message(&message)?;
+            // Tag the message with the frequency and mode it was received on:
+            if let Some(rig) = self.rig.as_mut() {
+                match rig.status() {
+                    Ok(status) => print!("  [{status}]"),
+                    Err(e) => warn!("{e:#}"),
+                }
+            }
+            println!(); // Move to the next line after the final message
+        }
+        if let Some(analyzer) = self.analyzer.as_mut() {
+            if let Some(report) = analyzer.report_text() {
+                print_lines(&report);
+            }
+            analyzer.clear();
+        }
+        self.pending = false;
+        self.decoder = get_decoder(self.dot_duration); // Reset decoder for a new message
+        if let Some(trainer) = self.trainer.as_mut() {
+            trainer.finish()?;
+        }
+        Ok((!message.is_empty()).then_some(message))
+    }
+}
+
+/// A key and the decoder of what it sends, for live keying where
+/// several inputs (a straight key, a keyer) key the same output.
+pub struct DecodedKey {
+    key: Box<dyn Key>,
+    decoder: LiveDecoder,
+    down: bool,
+    last_change: Duration,
+}
+
+impl DecodedKey {
+    pub fn new(key: Box<dyn Key>, decoder: LiveDecoder) -> Self {
+        Self {
+            key,
+            decoder,
+            down: false,
+            last_change: Duration::ZERO,
+        }
+    }
+
+    /// Keys the output at `at` (from any fixed start), if it changes.
+    pub fn set(&mut self, at: Duration, down: bool) -> anyhow::Result<()> {
+        if down != self.down {
+            self.key.set(down)?;
+            self.decoder
+                .signal(at.saturating_sub(self.last_change), self.down)?;
+            self.down = down;
+            self.last_change = at;
+        }
+        Ok(())
+    }
+
+    /// Nothing changed until `now`, finishes the message after a long
+    /// pause (and returns it).
+    pub fn idle(&mut self, now: Duration) -> io::Result<Option<String>> {
+        self.decoder.idle(now.saturating_sub(self.last_change))
+    }
+}
@@ -16,6 +16,7 @@ use textwrap::wrap;
 
 #[allow(clippy::too_many_arguments)]
 pub fn start_quiz(
+    player: &MorsePlayer,
     trials: u32,
     char_set: &str,
     dot_duration: u32,
@@ -24,7 +25,7 @@ pub fn start_quiz(
     randomize: bool,
     calibration: bool,
     baseline: u32,
-    rts_port: Option<&str>,
+    output_latency: u32,
 ) {
     let paragraph = match calibration {
         true => "Calibration process.\n\nThis process will measure your native keyboard typing skills to calculate your personal output latency. A series of characters will be displayed at the same time a tone is played. Enter the characters as fast as you can.\n".to_string(),
@@ -34,12 +35,11 @@ pub fn start_quiz(
     for line in wrap(&paragraph, 70) {
         println!("{}", line);
     }
-    let player = MorsePlayer::new();
 
     if calibration {
     } else {
         println!("Initializing audio (VVV) ...");
-        player.play("VVV", dot_duration, tone_freq, rts_port);
+        player.play("VVV", dot_duration, tone_freq);
     }
 
     if calibration {
@@ -93,7 +93,7 @@ pub fn start_quiz(
     }
 
     let results = reaction_time_quiz(
-        &player,
+        player,
         char_set,
         trials,
         dot_duration,
@@ -101,14 +101,18 @@ pub fn start_quiz(
         text,
         randomize,
         calibration,
-        if calibration { 0 } else { baseline },
-        rts_port,
+        if calibration {
+            0
+        } else {
+            baseline + output_latency
+        },
     );
     print_results(
         &results,
         Duration::from_millis(dot_duration.into()),
         calibration,
         if calibration { 0 } else { baseline },
+        if calibration { 0 } else { output_latency },
     );
 }
 
@@ -129,7 +133,6 @@ fn reaction_time_quiz(
     randomize: bool,
     calibration: bool,
     baseline: u32,
-    rts_port: Option<&str>,
 ) -> QuizResult {
     let mut prompts = Vec::new();
     let mut responses = Vec::new();
@@ -178,14 +181,9 @@ fn reaction_time_quiz(
         }
 
         if calibration {
-            player.play_nonblocking_tone(dot_duration, tone_freq, rts_port);
+            player.play_nonblocking_tone(dot_duration, tone_freq);
         } else {
-            player.play(
-                &target_letter.to_string(),
-                dot_duration,
-                tone_freq,
-                rts_port,
-            );
+            player.play(&target_letter.to_string(), dot_duration, tone_freq);
         }
 
         if text || calibration {
@@ -205,8 +203,7 @@ fn reaction_time_quiz(
                 match event.kind {
                     crossterm::event::KeyEventKind::Press if !key_processed => {
                         if let KeyCode::Char(input_char) = event.code {
-                            is_correct = input_char.to_ascii_uppercase()
-                                == target_letter.to_ascii_uppercase();
+                            is_correct = input_char.eq_ignore_ascii_case(&target_letter);
                             key_processed = true; // Block further processing until release
                         }
                         if event.code == KeyCode::Esc {
@@ -274,7 +271,13 @@ struct SummaryRow {
     times_incorrect: u32,
 }
 
-fn print_results(results: &QuizResult, dot_duration: Duration, calibration: bool, baseline: u32) {
+fn print_results(
+    results: &QuizResult,
+    dot_duration: Duration,
+    calibration: bool,
+    baseline: u32,
+    output_latency: u32,
+) {
     println!("\nTest complete!\n");
     let total = results.prompts.len();
     let correct = results
@@ -456,6 +459,9 @@ fn print_results(results: &QuizResult, dot_duration: Duration, calibration: bool
         println!("\n   code-smore fecr-quiz -b {average}")
     } else {
         println!("Baseline latency subtracted: {baseline}ms");
+        if output_latency > 0 {
+            println!("Audio output latency subtracted: {output_latency}ms");
+        }
         println!(
             "\nYour grade: {}
 Speed rating: {}",
@@ -0,0 +1,458 @@
+//! The fist analyzer (`--analyze`): the marks and spaces of what is
+//! keyed, classified as dits, dahs and gaps, with their timing, the
+//! ratios between them and histograms, and the habits they show.
+use crossterm::style::Stylize;
+use is_terminal::IsTerminal;
+use std::fmt::Write;
+use std::io;
+use std::time::Duration;
+use tabled::settings::style::Style;
+use tabled::{Table, Tabled};
+
+/// Width of the histogram bins, in dots
+const BIN_DOTS: f64 = 0.5;
+/// Width of the longest histogram bar
+const BAR_WIDTH: usize = 40;
+/// Coefficient of variation above which elements are uneven
+const MAX_SPREAD: f64 = 0.25;
+
+/// What a mark or space was meant to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    Dit,
+    Dah,
+    ElementGap,
+    CharacterGap,
+    WordGap,
+}
+
+impl Class {
+    const ALL: [Class; 5] = [
+        Class::Dit,
+        Class::Dah,
+        Class::ElementGap,
+        Class::CharacterGap,
+        Class::WordGap,
+    ];
+
+    /// The length of the element in dots, with perfect timing
+    fn ideal(self) -> f64 {
+        match self {
+            Class::Dit | Class::ElementGap => 1.,
+            Class::Dah | Class::CharacterGap => 3.,
+            Class::WordGap => 7.,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Class::Dit => "dit",
+            Class::Dah => "dah",
+            Class::ElementGap => "element gap",
+            Class::CharacterGap => "character gap",
+            Class::WordGap => "word gap",
+        }
+    }
+
+    /// Classifies a mark (or space) of `dots`, at the geometric middle
+    /// between the ideal lengths.
+    pub fn classify(mark: bool, dots: f64) -> Self {
+        match mark {
+            true if dots < 3_f64.sqrt() => Class::Dit,
+            true => Class::Dah,
+            false if dots < 3_f64.sqrt() => Class::ElementGap,
+            false if dots < 21_f64.sqrt() => Class::CharacterGap,
+            false => Class::WordGap,
+        }
+    }
+}
+
+/// The count, mean and standard deviation of durations in ms
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub count: usize,
+    pub mean: f64,
+    pub spread: f64,
+}
+
+impl Stats {
+    fn of(durations: &[f64]) -> Option<Self> {
+        if durations.is_empty() {
+            return None;
+        }
+        let count = durations.len();
+        let mean = durations.iter().sum::<f64>() / count as f64;
+        let variance = durations.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / count as f64;
+        Some(Self {
+            count,
+            mean,
+            spread: variance.sqrt(),
+        })
+    }
+}
+
+/// The analysis of a fist
+#[derive(Debug)]
+pub struct FistReport {
+    /// The stats of each element, in the order of `Class::ALL`
+    pub stats: [Option<Stats>; 5],
+    /// The speed of the dits
+    pub character_wpm: f64,
+    /// The speed of all the elements, gaps included
+    pub effective_wpm: f64,
+    pub habits: Vec<String>,
+}
+
+impl FistReport {
+    pub fn stats(&self, element: Class) -> Option<Stats> {
+        let index = Class::ALL.iter().position(|e| *e == element).unwrap();
+        self.stats[index]
+    }
+
+    /// The mean length of an element in dits (as sent)
+    pub fn ratio(&self, element: Class) -> Option<f64> {
+        let dit = self.stats(Class::Dit)?.mean;
+        Some(self.stats(element)?.mean / dit)
+    }
+}
+
+#[derive(Tabled)]
+struct ReportRow {
+    element: &'static str,
+    count: usize,
+    mean: String,
+    spread: String,
+    dits: String,
+    ideal: String,
+}
+
+/// Collects the marks and spaces of what is keyed.
+pub struct FistAnalyzer {
+    dot_duration: u32,
+    runs: Vec<(bool, f64)>, // Mark, ms
+}
+
+impl FistAnalyzer {
+    pub fn new(dot_duration: u32) -> Self {
+        Self {
+            dot_duration,
+            runs: Vec::new(),
+        }
+    }
+
+    /// The key was down (or up) for `duration`.
+    pub fn add(&mut self, duration: Duration, mark: bool) {
+        // Nothing is keyed before the first mark:
+        if duration.is_zero() || (!mark && self.runs.is_empty()) {
+            return;
+        }
+        self.runs.push((mark, duration.as_secs_f64() * 1000.));
+    }
+
+    pub fn clear(&mut self) {
+        self.runs.clear();
+    }
+
+    /// The length of a dit as sent, in ms: the mean of the shorter
+    /// marks, split from the dahs where the two cluster. Marks that
+    /// are all alike are dits or dahs depending on the configured dot.
+    fn dit(&self) -> Option<f64> {
+        let marks: Vec<f64> = self
+            .runs
+            .iter()
+            .filter(|(mark, _)| *mark)
+            .map(|(_, ms)| *ms)
+            .collect();
+        let min = marks.iter().copied().reduce(f64::min)?;
+        let max = marks.iter().copied().reduce(f64::max)?;
+        let mean = |marks: &[f64]| marks.iter().sum::<f64>() / marks.len() as f64;
+        if max < 2. * min {
+            let all = mean(&marks);
+            let dots = all / self.dot_duration as f64;
+            return Some(match Class::classify(true, dots) {
+                Class::Dit => all,
+                _ => all / Class::Dah.ideal(),
+            });
+        }
+        // Move the split to between the means of the dits and dahs,
+        // until it settles:
+        let mut split = (min * max).sqrt();
+        let mut dits = Vec::new();
+        for _ in 0..10 {
+            let dahs: Vec<f64>;
+            (dits, dahs) = marks.iter().partition(|ms| **ms < split);
+            let next = (mean(&dits) * mean(&dahs)).sqrt();
+            if next == split {
+                break;
+            }
+            split = next;
+        }
+        Some(mean(&dits))
+    }
+
+    /// The marks and spaces classified against the dit as sent, as
+    /// (what, mark, ms)
+    fn elements(&self) -> Vec<(Class, bool, f64)> {
+        let Some(dit) = self.dit() else {
+            return Vec::new();
+        };
+        self.runs
+            .iter()
+            .map(|&(mark, ms)| (Class::classify(mark, ms / dit), mark, ms))
+            // Pauses between messages aren't word gaps:
+            .filter(|&(_, mark, ms)| mark || ms / dit < 2. * Class::WordGap.ideal())
+            .collect()
+    }
+
+    pub fn report(&self) -> Option<FistReport> {
+        let elements = self.elements();
+        let durations = |element: Class| -> Vec<f64> {
+            elements
+                .iter()
+                .filter(|(e, _, _)| *e == element)
+                .map(|(_, _, ms)| *ms)
+                .collect()
+        };
+        let stats = Class::ALL.map(|element| Stats::of(&durations(element)));
+        let mut report = FistReport {
+            stats,
+            character_wpm: 0.,
+            effective_wpm: 0.,
+            habits: Vec::new(),
+        };
+        // The dit as sent, or else a third of the dah:
+        let dit = match (report.stats(Class::Dit), report.stats(Class::Dah)) {
+            (Some(dit), _) => dit.mean,
+            (None, Some(dah)) => dah.mean / 3.,
+            (None, None) => return None,
+        };
+        report.character_wpm = 1200. / dit;
+        let total: f64 = elements.iter().map(|(_, _, ms)| ms).sum();
+        let units: f64 = elements.iter().map(|(e, _, _)| e.ideal()).sum();
+        report.effective_wpm = 1200. * units / total;
+        report.habits = habits(&report);
+        Some(report)
+    }
+
+    /// A histogram of the marks (or spaces), in bins of half a dit as
+    /// sent
+    fn histogram(&self, mark: bool) -> String {
+        let dit = self.dit().unwrap_or(self.dot_duration as f64);
+        let mut bins: Vec<usize> = Vec::new();
+        for (_, _, ms) in self.elements().iter().filter(|(_, m, _)| *m == mark) {
+            let bin = (ms / dit / BIN_DOTS) as usize;
+            if bin >= bins.len() {
+                bins.resize(bin + 1, 0);
+            }
+            bins[bin] += 1;
+        }
+        let max = bins.iter().copied().max().unwrap_or(0).max(1);
+        let first = bins.iter().position(|count| *count > 0).unwrap_or(0);
+        let mut histogram = String::new();
+        for (bin, count) in bins.iter().enumerate().skip(first) {
+            let from = bin as f64 * BIN_DOTS;
+            let element = Class::classify(mark, from + BIN_DOTS / 2.);
+            let _ = writeln!(
+                histogram,
+                "{from:4.1}-{:<5.1}{:<BAR_WIDTH$} {count:3}  {}",
+                from + BIN_DOTS,
+                "█".repeat(count * BAR_WIDTH / max),
+                element.name()
+            );
+        }
+        histogram
+    }
+
+    /// The report as text: the stats, ratios, speeds, histograms and
+    /// habits.
+    pub fn report_text(&self) -> Option<String> {
+        let report = self.report()?;
+
+        let rows = Class::ALL.iter().filter_map(|&element| {
+            let stats = report.stats(element)?;
+            Some(ReportRow {
+                element: element.name(),
+                count: stats.count,
+                mean: format!("{:.0}ms", stats.mean),
+                spread: format!(
+                    "±{:.0}ms ({:.0}%)",
+                    stats.spread,
+                    stats.spread / stats.mean * 100.
+                ),
+                dits: report
+                    .ratio(element)
+                    .map_or("-".to_string(), |ratio| format!("{ratio:.2}")),
+                ideal: format!("{:.0}", element.ideal()),
+            })
+        });
+        let mut table = Table::new(rows);
+        let table = table.with(Style::rounded());
+
+        let mut text = String::new();
+        let _ = writeln!(text, "\nFist analysis:\n\n{table}");
+        let _ = writeln!(
+            text,
+            "\nCharacter speed: {:.1} WPM, effective speed: {:.1} WPM",
+            report.character_wpm, report.effective_wpm
+        );
+        let _ = writeln!(text, "\nMarks (dits):\n{}", self.histogram(true));
+        if self.elements().iter().any(|(_, mark, _)| !mark) {
+            let _ = writeln!(text, "Spaces (dits):\n{}", self.histogram(false));
+        }
+        // Habits are highlighted on a terminal:
+        let color = io::stdout().is_terminal();
+        if report.habits.is_empty() {
+            let well = "Well timed, keep it up!";
+            let _ = match color {
+                true => writeln!(text, "{}", well.green()),
+                false => writeln!(text, "{well}"),
+            };
+        }
+        for habit in &report.habits {
+            let habit = format!("! {habit}");
+            let _ = match color {
+                true => writeln!(text, "{}", habit.yellow().bold()),
+                false => writeln!(text, "{habit}"),
+            };
+        }
+        Some(text)
+    }
+}
+
+/// The habits a report shows, compared to the dits as sent.
+fn habits(report: &FistReport) -> Vec<String> {
+    let mut habits = Vec::new();
+    let mut check = |element: Class, low: f64, high: f64, short: &str, long: &str| {
+        if let Some(ratio) = report.ratio(element) {
+            let ideal = element.ideal();
+            let habit = match ratio {
+                ratio if ratio < low => short,
+                ratio if ratio > high => long,
+                _ => return,
+            };
+            habits.push(format!("{habit}: {ratio:.1} dits, should be {ideal:.0}"));
+        }
+    };
+    check(Class::Dah, 2.5, 3.5, "Short dahs", "Long dahs");
+    check(
+        Class::ElementGap,
+        0.75,
+        1.5,
+        "Short element gaps (heavy weighting)",
+        "Long element gaps: characters fall apart",
+    );
+    check(
+        Class::CharacterGap,
+        2.5,
+        4.,
+        "Short character spaces: characters run together",
+        "Long character spaces",
+    );
+    check(
+        Class::WordGap,
+        5.5,
+        10.,
+        "Short word spaces: words run together",
+        "Long word spaces",
+    );
+    for element in [Class::Dit, Class::Dah] {
+        let uneven = report
+            .stats(element)
+            .is_some_and(|stats| stats.count > 1 && stats.spread / stats.mean > MAX_SPREAD);
+        if uneven {
+            habits.push(format!("Uneven {}s", element.name()));
+        }
+    }
+    habits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::morse::encode_morse;
+    use crate::timings::keyed;
+
+    #[test]
+    fn test_fist_report() {
+        // PARIS with dahs of 4 dots and character gaps of 2:
+        let mut runs: Vec<(bool, u32)> = Vec::new();
+        for (freq, ms) in encode_morse("PARIS PARIS", 60, 440.) {
+            match runs.last_mut() {
+                Some((false, space)) if freq == 0. => *space += ms,
+                _ => runs.push((freq > 0., ms)),
+            }
+        }
+        let mut fist = FistAnalyzer::new(60);
+        for (mark, ms) in runs {
+            let ms = match (mark, ms) {
+                (true, 180) => 240,
+                (false, 300) => 120,
+                (false, ms) if ms > 300 => 420, // The word gap
+                (_, ms) => ms,
+            };
+            fist.add(Duration::from_millis(ms.into()), mark);
+        }
+        let report = fist.report().unwrap();
+        assert_eq!(report.stats(Class::Dit).unwrap().count, 20);
+        assert_eq!(report.stats(Class::Dah).unwrap().count, 8);
+        assert_eq!(report.stats(Class::CharacterGap).unwrap().count, 8);
+        assert_eq!(report.stats(Class::WordGap).unwrap().count, 1);
+        assert_eq!(report.ratio(Class::Dah), Some(4.));
+        assert_eq!(report.character_wpm, 20.);
+        assert_eq!(
+            report.habits,
+            [
+                "Long dahs: 4.0 dits, should be 3",
+                "Short character spaces: characters run together: 2.0 dits, should be 3"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fist_classifies_against_sent_dit() {
+        // Keyed at 30 WPM with the speed set to 12 WPM, after a pause
+        // and a key release that took no time:
+        let mut fist = FistAnalyzer::new(100);
+        fist.add(Duration::from_secs(5), false);
+        fist.add(Duration::ZERO, false);
+        for (mark, duration) in keyed("PARIS", 40) {
+            fist.add(duration, mark);
+        }
+        let report = fist.report().unwrap();
+        assert_eq!(report.stats(Class::Dit).unwrap().count, 10);
+        assert_eq!(report.stats(Class::Dah).unwrap().count, 4);
+        assert_eq!(report.ratio(Class::Dah), Some(3.));
+        assert_eq!(report.character_wpm, 30.);
+        assert!(report.habits.is_empty(), "habits: {:?}", report.habits);
+
+        // All alike, the marks are dits or dahs by the configured dot:
+        let mut fist = FistAnalyzer::new(100);
+        for _ in 0..3 {
+            fist.add(Duration::from_millis(300), true);
+            fist.add(Duration::from_millis(100), false);
+        }
+        let report = fist.report().unwrap();
+        assert_eq!(report.stats(Class::Dah).unwrap().count, 3);
+        assert!(report.stats(Class::Dit).is_none());
+    }
+
+    #[test]
+    fn test_fist_long_character_spaces() {
+        // Character gaps of 4.5 dots, still short of a word gap:
+        let mut fist = FistAnalyzer::new(60);
+        for (mark, duration) in keyed("PARIS PARIS", 60) {
+            let duration = match (mark, duration.as_millis()) {
+                (false, 180) => Duration::from_millis(270),
+                _ => duration,
+            };
+            fist.add(duration, mark);
+        }
+        let report = fist.report().unwrap();
+        assert_eq!(report.stats(Class::CharacterGap).unwrap().count, 8);
+        assert_eq!(report.stats(Class::WordGap).unwrap().count, 1);
+        assert_eq!(
+            report.habits,
+            ["Long character spaces: 4.5 dits, should be 3"]
+        );
+    }
+}
@@ -1,100 +1,660 @@
 #![allow(unused_imports)]
-use crate::morse::text_to_morse;
+use crate::decode::LiveDecoder;
+use crate::key::Key;
+use crate::keyer::{self, Keyer, KeyerSettings};
 use crate::prelude::*;
-use morse_codec::decoder::{Decoder, MorseDecoder};
-#[cfg(feature = "gpio")]
-use rppal;
+use crate::shutdown::{self, GuardedKey};
+use anyhow::Context;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::time::Duration;
 use std::time::Instant;
 
-#[allow(dead_code)]
-fn get_decoder(dot_duration: u32) -> MorseDecoder<9999> {
-    Decoder::<9999>::new()
-        .with_reference_short_ms(dot_duration as u16)
-        .build()
+/// Internal bias resistor of a GPIO input pin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GpioPull {
+    #[default]
+    None, // Needs an external resistor
+    Up,
+    Down,
+}
+
+impl FromStr for GpioPull {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "none" => GpioPull::None,
+            "up" => GpioPull::Up,
+            "down" => GpioPull::Down,
+            _ => anyhow::bail!("unknown GPIO pull `{s}` (expected none, up or down)"),
+        })
+    }
+}
+
+/// How key and paddle contacts are read from GPIO input pins
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(not(feature = "gpio"), allow(dead_code))]
+pub struct GpioInput {
+    pub chip: Option<String>, // GPIO character device (else the Raspberry Pi's GPIO)
+    pub pull: GpioPull,
+    pub active_high: bool, // The contact is closed when the pin is high (rather than low)
+    pub debounce: Duration, // A change only counts once the pin has been stable this long (0 disables)
+}
+
+/// How GPIO output pins are keyed
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(not(feature = "gpio"), allow(dead_code))]
+pub struct GpioOutput {
+    pub chip: Option<String>, // GPIO character device (else the Raspberry Pi's GPIO)
+    pub active_low: bool,     // Low while the key is down, e.g. for opto-isolated keying circuits
+}
+
+/// Path of a GPIO character device given as `/dev/gpiochip1`,
+/// `gpiochip1` or just `1`
+#[cfg(feature = "gpio")]
+fn chip_path(chip: &str) -> String {
+    if chip.starts_with('/') {
+        chip.to_string()
+    } else if chip.chars().all(|c| c.is_ascii_digit()) {
+        format!("/dev/gpiochip{chip}")
+    } else {
+        format!("/dev/{chip}")
+    }
+}
+
+#[cfg(feature = "gpio")]
+fn chip_line(chip: &str, pin_number: u8) -> anyhow::Result<gpio_cdev::Line> {
+    let path = chip_path(chip);
+    gpio_cdev::Chip::new(&path)
+        .with_context(|| format!("opening GPIO chip {path}"))?
+        .get_line(pin_number.into())
+        .with_context(|| format!("getting line {pin_number} of GPIO chip {path}"))
+}
+
+/// Debounces a contact by settle time: a change only counts once the
+/// contact has stayed in its new state for the debounce time, so bounces
+/// and short spikes are ignored.
+#[cfg_attr(not(feature = "gpio"), allow(dead_code))]
+struct Debouncer {
+    state: bool,
+    burst: Option<(bool, Duration)>, // The latest level, and when its burst of edges started
+}
+
+#[cfg_attr(not(feature = "gpio"), allow(dead_code))]
+impl Debouncer {
+    fn new(state: bool) -> Self {
+        Self { state, burst: None }
+    }
+
+    /// Feeds an edge of the contact (its new level and timestamp).
+    fn edge(&mut self, level: bool, at: Duration) {
+        let start = self.burst.map_or(at, |(_, start)| start);
+        self.burst = Some((level, start));
+    }
+
+    /// Whether edges are waiting for the contact to settle
+    fn is_pending(&self) -> bool {
+        self.burst.is_some()
+    }
+
+    /// Call once no edge came for the debounce time. Returns the change,
+    /// if any, with the timestamp of the first edge of the burst.
+    fn settle(&mut self) -> Option<(bool, Duration)> {
+        let (level, start) = self.burst.take()?;
+        if level == self.state {
+            return None;
+        }
+        self.state = level;
+        Some((level, start))
+    }
+}
+
+/// A key or paddle contact on a GPIO input pin. Changes are reported
+/// by interrupts carrying the kernel's timestamp of each edge, and
+/// debounced by a thread that passes on the accepted ones.
+#[cfg(feature = "gpio")]
+struct GpioContact {
+    edges: mpsc::Receiver<(bool, Duration)>,
+    closed: Arc<AtomicBool>,
+    _pin: Option<rppal::gpio::InputPin>, // Keeps the Raspberry Pi interrupt alive
+}
+
+#[cfg(feature = "gpio")]
+impl GpioContact {
+    fn new(pin_number: u8, input: &GpioInput) -> anyhow::Result<Self> {
+        let (raw_sender, raw) = mpsc::channel();
+        let active_high = input.active_high;
+        let (closed, pin) = match &input.chip {
+            Some(chip) => (Self::read_chip(chip, pin_number, input, raw_sender)?, None),
+            None => {
+                let pin = rppal::gpio::Gpio::new()
+                    .context("accessing GPIO")?
+                    .get(pin_number)
+                    .with_context(|| format!("getting GPIO pin {pin_number}"))?;
+                let mut pin = match input.pull {
+                    GpioPull::None => pin.into_input(),
+                    GpioPull::Up => pin.into_input_pullup(),
+                    GpioPull::Down => pin.into_input_pulldown(),
+                };
+                pin.set_async_interrupt(rppal::gpio::Trigger::Both, None, move |event| {
+                    let rising = event.trigger == rppal::gpio::Trigger::RisingEdge;
+                    let _ = raw_sender.send((rising == active_high, event.timestamp));
+                })
+                .context("setting up the GPIO interrupt")?;
+                (pin.is_high() == active_high, Some(pin))
+            }
+        };
+        let closed = Arc::new(AtomicBool::new(closed));
+        let (sender, edges) = mpsc::channel();
+        let state = closed.clone();
+        let debounce = input.debounce;
+        std::thread::spawn(move || {
+            let mut debouncer = Debouncer::new(state.load(Ordering::SeqCst));
+            loop {
+                let edge = match debouncer.is_pending() && !debounce.is_zero() {
+                    true => raw.recv_timeout(debounce),
+                    false => raw.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+                };
+                let settled = match edge {
+                    Ok((level, at)) => {
+                        debouncer.edge(level, at);
+                        debounce.is_zero()
+                    }
+                    // No edge for the debounce time:
+                    Err(mpsc::RecvTimeoutError::Timeout) => true,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                };
+                let Some((closed, at)) = settled.then(|| debouncer.settle()).flatten() else {
+                    continue;
+                };
+                state.store(closed, Ordering::SeqCst);
+                if sender.send((closed, at)).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Self {
+            edges,
+            closed,
+            _pin: pin,
+        })
+    }
+
+    /// Reads the edges of a character device line on a thread, and
+    /// returns whether the contact is closed now.
+    fn read_chip(
+        chip: &str,
+        pin_number: u8,
+        input: &GpioInput,
+        raw: mpsc::Sender<(bool, Duration)>,
+    ) -> anyhow::Result<bool> {
+        use gpio_cdev::{EventRequestFlags, EventType, LineRequestFlags};
+        if input.pull != GpioPull::None {
+            warn!("GPIO character devices don't set pull resistors, ignoring --gpio-pull.");
+        }
+        let mut events = chip_line(chip, pin_number)?
+            .events(
+                LineRequestFlags::INPUT,
+                EventRequestFlags::BOTH_EDGES,
+                "code-smore",
+            )
+            .context("requesting GPIO line events")?;
+        let active_high = input.active_high;
+        let closed = (events.get_value()? == 1) == active_high;
+        std::thread::spawn(move || {
+            for event in &mut events {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        error!("Reading GPIO line events: {e}");
+                        return;
+                    }
+                };
+                let rising = event.event_type() == EventType::RisingEdge;
+                let at = Duration::from_nanos(event.timestamp());
+                if raw.send((rising == active_high, at)).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(closed)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Waits up to `timeout` for the contact to change, and returns its
+    /// new state with the kernel's timestamp of the edge.
+    fn wait(&mut self, timeout: Duration) -> anyhow::Result<Option<(bool, Duration)>> {
+        match self.edges.recv_timeout(timeout) {
+            Ok(edge) => Ok(Some(edge)),
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                anyhow::bail!("stopped reading the GPIO line")
+            }
+        }
+    }
+
+    /// The state of the contact, followed in the background
+    fn watch(&self) -> Arc<AtomicBool> {
+        self.closed.clone()
+    }
+}
+
+/// A GPIO output line
+#[cfg(feature = "gpio")]
+enum OutputLine {
+    Rppal(rppal::gpio::OutputPin),
+    Chip(gpio_cdev::LineHandle),
+}
+
+/// Keys a GPIO output pin (high while the key is down, or low if
+/// `active_low`)
+#[cfg(feature = "gpio")]
+pub struct GpioKey {
+    line: OutputLine,
+    active_low: bool,
+}
+
+#[cfg(feature = "gpio")]
+impl GpioKey {
+    pub fn new(pin_number: u8, output: &GpioOutput) -> anyhow::Result<Self> {
+        let idle = u8::from(output.active_low);
+        let line = match &output.chip {
+            Some(chip) => OutputLine::Chip(
+                chip_line(chip, pin_number)?
+                    .request(gpio_cdev::LineRequestFlags::OUTPUT, idle, "code-smore")
+                    .context("requesting GPIO output line")?,
+            ),
+            None => {
+                let pin = rppal::gpio::Gpio::new()?.get(pin_number)?;
+                let mut pin = match output.active_low {
+                    true => pin.into_output_high(),
+                    false => pin.into_output_low(),
+                };
+                // Left as an input, the line would float (and may key an
+                // active low circuit) once closed:
+                pin.set_reset_on_drop(false);
+                OutputLine::Rppal(pin)
+            }
+        };
+        let mut key = Self {
+            line,
+            active_low: output.active_low,
+        };
+        key.set(false)?;
+        Ok(key)
+    }
+}
+
+#[cfg(feature = "gpio")]
+impl Key for GpioKey {
+    fn set(&mut self, down: bool) -> anyhow::Result<()> {
+        let high = down != self.active_low;
+        match &mut self.line {
+            OutputLine::Rppal(pin) => pin.write(high.into()),
+            OutputLine::Chip(line) => line.set_value(high.into())?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "gpio"))]
+pub struct GpioKey;
+
+#[cfg(not(feature = "gpio"))]
+impl GpioKey {
+    pub fn new(_pin_number: u8, _output: &GpioOutput) -> anyhow::Result<Self> {
+        anyhow::bail!("The GPIO feature is not enabled in this crate build")
+    }
+}
+
+#[cfg(not(feature = "gpio"))]
+impl Key for GpioKey {
+    fn set(&mut self, _down: bool) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// How a buzzer pin makes its tone
+#[cfg(feature = "gpio")]
+enum ToneLine {
+    /// Hardware PWM, on the Raspberry Pi's PWM capable pins
+    Pwm(rppal::pwm::Pwm),
+    /// Software PWM on any Raspberry Pi pin
+    Pin(rppal::gpio::OutputPin),
+    /// A thread toggling a character device line
+    Chip {
+        state: Arc<ChipTone>,
+        thread: Option<std::thread::JoinHandle<()>>,
+    },
+}
+
+/// Shared with the thread toggling a character device line
+#[cfg(feature = "gpio")]
+struct ChipTone {
+    on: AtomicBool,
+    stop: AtomicBool,
+    half_period: Mutex<Duration>,
+}
+
+#[cfg(feature = "gpio")]
+struct Buzzer {
+    line: ToneLine,
+    freq: f32,
+    down: bool,
+}
+
+#[cfg(feature = "gpio")]
+impl Buzzer {
+    fn apply(&mut self) -> anyhow::Result<()> {
+        let freq = f64::from(self.freq);
+        match &mut self.line {
+            ToneLine::Pwm(pwm) if self.down => {
+                pwm.set_frequency(freq, 0.5)?;
+                pwm.enable()?;
+            }
+            ToneLine::Pwm(pwm) => pwm.disable()?,
+            ToneLine::Pin(pin) if self.down => pin.set_pwm_frequency(freq, 0.5)?,
+            ToneLine::Pin(pin) => pin.clear_pwm()?,
+            ToneLine::Chip { state, thread } => {
+                *state.half_period.lock().unwrap() = Duration::from_secs_f64(0.5 / freq);
+                state.on.store(self.down, Ordering::SeqCst);
+                if let Some(thread) = thread {
+                    thread.thread().unpark();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "gpio")]
+impl Drop for Buzzer {
+    fn drop(&mut self) {
+        self.down = false;
+        let _ = self.apply();
+        if let ToneLine::Chip { state, thread } = &mut self.line {
+            state.stop.store(true, Ordering::SeqCst);
+            if let Some(thread) = thread.take() {
+                thread.thread().unpark();
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+/// The buzzer as registered with `shutdown::guard`
+#[cfg(feature = "gpio")]
+struct BuzzerKey(Arc<Mutex<Buzzer>>);
+
+#[cfg(feature = "gpio")]
+impl Key for BuzzerKey {
+    fn set(&mut self, down: bool) -> anyhow::Result<()> {
+        let mut buzzer = self.0.lock().unwrap();
+        if buzzer.down != down {
+            buzzer.down = down;
+            buzzer.apply()?;
+        }
+        Ok(())
+    }
+}
+
+/// Sounds a piezo buzzer on a GPIO output pin at a tone frequency while
+/// the key is down, for a sidetone without a sound card. Clones share
+/// the pin.
+#[cfg(feature = "gpio")]
+#[derive(Clone)]
+pub struct GpioBuzzer {
+    buzzer: Arc<Mutex<Buzzer>>,
+    key: Arc<Mutex<GuardedKey>>, // Silences the buzzer on shutdown
+}
+
+#[cfg(feature = "gpio")]
+impl GpioBuzzer {
+    pub fn new(pin_number: u8, output: &GpioOutput, tone_freq: f32) -> anyhow::Result<Self> {
+        let line = match &output.chip {
+            Some(chip) => {
+                let idle = u8::from(output.active_low);
+                let line = chip_line(chip, pin_number)?
+                    .request(gpio_cdev::LineRequestFlags::OUTPUT, idle, "code-smore")
+                    .context("requesting GPIO buzzer line")?;
+                let state = Arc::new(ChipTone {
+                    on: AtomicBool::new(false),
+                    stop: AtomicBool::new(false),
+                    half_period: Mutex::new(Duration::from_secs_f32(0.5 / tone_freq)),
+                });
+                let thread_state = state.clone();
+                let thread = std::thread::spawn(move || {
+                    let mut level = idle;
+                    while !thread_state.stop.load(Ordering::SeqCst) {
+                        if thread_state.on.load(Ordering::SeqCst) {
+                            level ^= 1;
+                            let _ = line.set_value(level);
+                            let half_period = *thread_state.half_period.lock().unwrap();
+                            std::thread::sleep(half_period);
+                        } else {
+                            if level != idle {
+                                level = idle;
+                                let _ = line.set_value(level);
+                            }
+                            std::thread::park();
+                        }
+                    }
+                    let _ = line.set_value(idle);
+                });
+                ToneLine::Chip {
+                    state,
+                    thread: Some(thread),
+                }
+            }
+            None => {
+                use rppal::pwm::{Channel, Polarity, Pwm};
+                let channel = match pin_number {
+                    12 | 18 => Some(Channel::Pwm0),
+                    13 | 19 => Some(Channel::Pwm1),
+                    _ => None,
+                };
+                let freq = f64::from(tone_freq);
+                let pwm = channel.map(|channel| {
+                    Pwm::with_frequency(channel, freq, 0.5, Polarity::Normal, false)
+                });
+                match pwm {
+                    Some(Ok(pwm)) => ToneLine::Pwm(pwm),
+                    _ => {
+                        if let Some(Err(e)) = pwm {
+                            debug!(
+                                "No hardware PWM on GPIO pin {pin_number} ({e}), using software PWM"
+                            );
+                        }
+                        ToneLine::Pin(rppal::gpio::Gpio::new()?.get(pin_number)?.into_output_low())
+                    }
+                }
+            }
+        };
+        let buzzer = Arc::new(Mutex::new(Buzzer {
+            line,
+            freq: tone_freq,
+            down: false,
+        }));
+        let name = format!("GPIO buzzer pin {pin_number}");
+        let key = shutdown::guard(&name, BuzzerKey(buzzer.clone()), false);
+        Ok(Self {
+            buzzer,
+            key: Arc::new(Mutex::new(key)),
+        })
+    }
+
+    /// Changes the tone frequency, even while it is sounding.
+    pub fn set_frequency(&self, tone_freq: f32) -> anyhow::Result<()> {
+        let mut buzzer = self.buzzer.lock().unwrap();
+        buzzer.freq = tone_freq;
+        if buzzer.down {
+            buzzer.apply()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "gpio")]
+impl Key for GpioBuzzer {
+    fn set(&mut self, down: bool) -> anyhow::Result<()> {
+        self.key.lock().unwrap().set(down)
+    }
+}
+
+#[cfg(not(feature = "gpio"))]
+#[derive(Clone)]
+pub struct GpioBuzzer;
+
+#[cfg(not(feature = "gpio"))]
+impl GpioBuzzer {
+    pub fn new(_pin_number: u8, _output: &GpioOutput, _tone_freq: f32) -> anyhow::Result<Self> {
+        anyhow::bail!("The GPIO feature is not enabled in this crate build")
+    }
+
+    pub fn set_frequency(&self, _tone_freq: f32) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "gpio"))]
+impl Key for GpioBuzzer {
+    fn set(&mut self, _down: bool) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
 
 #[cfg(feature = "gpio")]
 pub fn gpio_receive(
     dot_duration: u32,
     pin_number: u8,
-    output_morse: bool,
-) -> Result<(), std::io::Error> {
-    let pin = rppal::gpio::Gpio::new()
-        .expect("Failed to access GPIO")
-        .get(pin_number)
-        .expect("Failed to get GPIO pin")
-        .into_input();
-
-    let mut decoder = get_decoder(dot_duration);
+    input: GpioInput,
+    mut sidetone: Box<dyn Key>,
+    mut decoder: LiveDecoder,
+) -> anyhow::Result<()> {
+    let mut key = GpioContact::new(pin_number, &input)?;
+
+    let mut key_down = key.is_closed();
+    let mut last_edge: Option<Duration> = None; // Kernel timestamp of the last change
     let mut last_signal_change = Instant::now();
-    let mut last_signal_state = !pin.is_low(); // Normally high logic
-    let mut message_pending = false; // Tracks if there's a pending message to finalize
 
     clear_screen();
     info!("Receiving morse code from GPIO pin {pin_number} - Press Ctrl-C to stop.");
 
     loop {
-        let current_state = !pin.is_low(); // Invert the signal logic
-
-        // Handle state changes
-        if current_state != last_signal_state {
-            let duration = last_signal_change.elapsed().as_millis();
-            debug!(
-                "State changed: {:?} -> {:?}, Duration: {} ms",
-                last_signal_state, current_state, duration
-            );
-            decoder.signal_event(duration as u16, current_state);
-            last_signal_change = Instant::now();
-            last_signal_state = current_state;
-
-            message_pending = true; // New signal indicates a valid message is being processed
-
-            // Print the current message on the same line
-            let message = decoder.message.as_str().trim().to_string();
-            if !message.is_empty() {
-                if output_morse {
-                    print!("\r\x1b[K{}", text_to_morse(&message));
-                } else {
-                    print!("\r\x1b[K{message}");
-                }
-                std::io::Write::flush(&mut std::io::stdout())?;
+        // Sleep until the key changes (an edge can repeat the current
+        // state after a missed one), waking up now and then to check
+        // for inactivity:
+        let timeout = Duration::from_millis(dot_duration.into());
+        let change = key.wait(timeout)?.filter(|(down, _)| *down != key_down);
+        if let Some((down, at)) = change {
+            sidetone.set(down)?;
+            // Nothing was timed before the first edge:
+            if let Some(last) = last_edge {
+                decoder.signal(at.saturating_sub(last), key_down)?;
             }
+            key_down = down;
+            last_edge = Some(at);
+            last_signal_change = Instant::now();
         }
 
         // Check for inactivity
-        let elapsed = last_signal_change.elapsed();
-        if elapsed > Duration::from_millis(6 * 7 * dot_duration as u64) && message_pending {
-            // Inactivity detected, finalize the pending message
-            decoder.signal_event_end(false);
-            let message = decoder.message.as_str().trim().to_string();
-            if !message.is_empty() {
-                debug!("Inactivity detected. Final message: {:?}", message);
-                // Clear the current line before printing the final message
-                if output_morse {
-                    print!("\r\x1b[K{}", text_to_morse(&message));
-                } else {
-                    print!("\r\x1b[K{message}");
-                }
-                println!(); // Move to the next line after the final message
-            }
-            message_pending = false; // Reset pending message flag
-            decoder = get_decoder(dot_duration); // Reset decoder for a new message
-        }
-
-        // Prevent CPU overuse
-        std::thread::sleep(Duration::from_millis(1));
+        decoder.idle(last_signal_change.elapsed())?;
     }
 }
 
+/// Runs a paddle on two GPIO input pins through the keyer, keying
+/// `key` (sidetone, output pins, PTT, ...) and decoding what is sent.
+#[cfg(feature = "gpio")]
+pub fn gpio_paddle_receive(
+    dit_pin: u8,
+    dah_pin: u8,
+    input: GpioInput,
+    settings: KeyerSettings,
+    mut key: Box<dyn Key>,
+    mut decoder: LiveDecoder,
+) -> anyhow::Result<()> {
+    let dit = GpioContact::new(dit_pin, &input)?;
+    let dah = GpioContact::new(dah_pin, &input)?;
+    let dit_closed = dit.watch();
+    let dah_closed = dah.watch();
+
+    let mut keyer = Keyer::new(settings);
+    let mut last_change = Duration::ZERO;
+
+    clear_screen();
+    info!(
+        "Keying from the paddle on GPIO pins {dit_pin} (dit) and {dah_pin} (dah) - Press Ctrl-C to stop."
+    );
+    keyer::run(
+        &mut keyer,
+        || {
+            Ok((
+                dit_closed.load(Ordering::SeqCst),
+                dah_closed.load(Ordering::SeqCst),
+            ))
+        },
+        key.as_mut(),
+        &AtomicBool::new(false),
+        |now, events| {
+            for event in events {
+                decoder.signal(event.at - last_change, !event.down)?;
+                last_change = event.at;
+            }
+            decoder.idle(now - last_change)?;
+            Ok(())
+        },
+    )
+}
+
 #[cfg(not(feature = "gpio"))]
 pub fn gpio_receive(
     _dot_duration: u32,
     _pin_number: u8,
-    _output_morse: bool,
-) -> Result<(), std::io::Error> {
-    return Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        "The GPIO feature is not enabled in this crate build",
-    ));
+    _input: GpioInput,
+    _sidetone: Box<dyn Key>,
+    _decoder: LiveDecoder,
+) -> anyhow::Result<()> {
+    anyhow::bail!("The GPIO feature is not enabled in this crate build")
+}
+
+#[cfg(not(feature = "gpio"))]
+pub fn gpio_paddle_receive(
+    _dit_pin: u8,
+    _dah_pin: u8,
+    _input: GpioInput,
+    _settings: KeyerSettings,
+    _key: Box<dyn Key>,
+    _decoder: LiveDecoder,
+) -> anyhow::Result<()> {
+    anyhow::bail!("The GPIO feature is not enabled in this crate build")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debouncer_ignores_bounces_and_spikes() {
+        let at = Duration::from_millis;
+        let mut debouncer = Debouncer::new(false);
+        // The contact bounces when closing, then settles:
+        debouncer.edge(true, at(0));
+        debouncer.edge(false, at(1));
+        debouncer.edge(true, at(2));
+        assert!(debouncer.is_pending());
+        assert_eq!(debouncer.settle(), Some((true, at(0))));
+        assert!(!debouncer.is_pending());
+        // A spike on the closed contact doesn't open it:
+        debouncer.edge(false, at(50));
+        debouncer.edge(true, at(51));
+        assert_eq!(debouncer.settle(), None);
+        // Opening counts from its first edge:
+        debouncer.edge(false, at(100));
+        assert_eq!(debouncer.settle(), Some((false, at(100))));
+        assert_eq!(debouncer.settle(), None);
+    }
 }
@@ -0,0 +1,230 @@
+//! Keys and paddles on input devices: Linux input devices
+//! (`/dev/input/event*`: USB paddle adapters, keyboards, foot switches)
+//! and MIDI adapters. Events are read with the timestamps of the
+//! device's driver, so the timing doesn't depend on the terminal, and no
+//! window needs focus.
+use crate::decode::{DecodedKey, LiveDecoder};
+use crate::key::Key;
+use crate::keyer::{Keyer, KeyerSettings};
+#[cfg(all(target_os = "linux", feature = "evdev"))]
+use crate::prelude::*;
+#[cfg(all(target_os = "linux", feature = "evdev"))]
+use anyhow::{anyhow, Context};
+use std::sync::mpsc;
+use std::time::Duration;
+#[cfg(all(target_os = "linux", feature = "evdev"))]
+use std::time::SystemTime;
+
+/// The key codes of the contacts on an input device, by name (e.g.
+/// `KEY_LEFTCTRL`, see `evtest`) or number
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(not(all(target_os = "linux", feature = "evdev")), allow(dead_code))]
+pub struct InputContacts {
+    pub straight: Option<String>,
+    pub dit: Option<String>,
+    pub dah: Option<String>,
+}
+
+/// A straight key or paddle contact
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Contact {
+    Straight,
+    Dit,
+    Dah,
+}
+
+/// A contact closing or opening, at a time from any fixed start
+#[derive(Debug, Clone, Copy)]
+pub struct ContactEvent {
+    pub contact: Contact,
+    pub closed: bool,
+    pub at: Duration,
+}
+
+/// Keys `key` (sidetone, output lines, PTT, ...) from contact events,
+/// the paddles through the keyer, and decodes what is sent, until the
+/// events stop. `clock` tells the current time, from the same start
+/// as the events.
+pub fn receive_contacts(
+    events: mpsc::Receiver<anyhow::Result<ContactEvent>>,
+    clock: impl Fn() -> Duration,
+    settings: KeyerSettings,
+    key: Box<dyn Key>,
+    decoder: LiveDecoder,
+) -> anyhow::Result<()> {
+    let mut keyer = Keyer::new(settings);
+    let mut keying = DecodedKey::new(key, decoder);
+    let (mut straight, mut dit, mut dah) = (false, false, false);
+    let mut keyer_down = false;
+    let mut now = Duration::ZERO;
+    let dot = Duration::from_millis(settings.dot_duration.into());
+
+    loop {
+        // Wait for the next event, waking up for the keyer's next
+        // element edge and now and then to finish the message:
+        let timeout = match keyer.next_deadline() {
+            Some(deadline) => deadline.saturating_sub(clock()).min(dot),
+            None => dot,
+        };
+        let event = match events.recv_timeout(timeout) {
+            Ok(event) => Some(event?),
+            Err(mpsc::RecvTimeoutError::Timeout) => None,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+
+        // Changes take effect at the device's timestamp of the event
+        // (never going backwards):
+        now = now.max(event.map_or_else(&clock, |event| event.at));
+        if let Some(event) = event {
+            match event.contact {
+                Contact::Straight => straight = event.closed,
+                Contact::Dit => dit = event.closed,
+                Contact::Dah => dah = event.closed,
+            }
+        }
+
+        for event in keyer.update(now, dit, dah) {
+            keyer_down = event.down;
+            keying.set(event.at, keyer_down || straight)?;
+        }
+        keying.set(now, keyer_down || straight)?;
+        keying.idle(now)?;
+    }
+    keying.set(now.max(clock()), false)
+}
+
+/// Parses a key code: `KEY_LEFTCTRL`, `leftctrl`, `BTN_LEFT` or `29`.
+#[cfg(all(target_os = "linux", feature = "evdev"))]
+fn key_code(name: &str) -> anyhow::Result<evdev::KeyCode> {
+    let upper = name.trim().to_ascii_uppercase();
+    if let Ok(code) = upper.parse::<u16>() {
+        return Ok(evdev::KeyCode::new(code));
+    }
+    let prefixed = match upper.starts_with("KEY_") || upper.starts_with("BTN_") {
+        true => upper,
+        false => format!("KEY_{upper}"),
+    };
+    prefixed.parse().map_err(|_| {
+        anyhow!("unknown key code `{name}` (see `evtest` for the codes a device sends)")
+    })
+}
+
+/// Keys `key` (sidetone, output lines, PTT, ...) from the contacts on
+/// an input device, the paddles through the keyer, and decodes what is
+/// sent. With `grab`, the device's events go to nothing else (e.g. the
+/// Ctrl keys of a paddle adapter don't reach the desktop).
+#[cfg(all(target_os = "linux", feature = "evdev"))]
+pub fn input_receive(
+    path: &str,
+    contacts: &InputContacts,
+    grab: bool,
+    settings: KeyerSettings,
+    key: Box<dyn Key>,
+    decoder: LiveDecoder,
+) -> anyhow::Result<()> {
+    let mut codes = Vec::new();
+    for (name, contact) in [
+        (&contacts.straight, Contact::Straight),
+        (&contacts.dit, Contact::Dit),
+        (&contacts.dah, Contact::Dah),
+    ] {
+        if let Some(name) = name {
+            codes.push((key_code(name)?, contact));
+        }
+    }
+
+    let mut device =
+        evdev::Device::open(path).with_context(|| format!("opening input device {path}"))?;
+    let device_name = device.name().unwrap_or("unnamed").to_string();
+    for (code, _) in &codes {
+        let supported = device
+            .supported_keys()
+            .is_some_and(|keys| keys.contains(*code));
+        if !supported {
+            warn!("Input device {path} ({device_name}) doesn't report {code:?}");
+        }
+    }
+    if grab {
+        device
+            .grab()
+            .with_context(|| format!("grabbing input device {path}"))?;
+    }
+
+    // Read the events in a thread, so the keyer can time its elements
+    // while waiting for the next one:
+    let start = SystemTime::now();
+    let since_start = move |time: SystemTime| time.duration_since(start).unwrap_or_default();
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || loop {
+        let events = match device.fetch_events() {
+            Ok(events) => events,
+            Err(e) => {
+                let error = anyhow::Error::new(e).context("reading the input device");
+                let _ = sender.send(Err(error));
+                return;
+            }
+        };
+        // Values are 1 for a press, 0 for a release and 2 for a repeat:
+        let changes = events.filter_map(|event| match event.destructure() {
+            evdev::EventSummary::Key(_, code, value @ (0 | 1)) => {
+                Some((code, value == 1, since_start(event.timestamp())))
+            }
+            _ => None,
+        });
+        for (code, closed, at) in changes {
+            for &(_, contact) in codes.iter().filter(|(c, _)| *c == code) {
+                let event = ContactEvent {
+                    contact,
+                    closed,
+                    at,
+                };
+                if sender.send(Ok(event)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    clear_screen();
+    info!("Keying from input device {path} ({device_name}) - Press Ctrl-C to stop.");
+    receive_contacts(
+        receiver,
+        move || since_start(SystemTime::now()),
+        settings,
+        key,
+        decoder,
+    )
+}
+
+#[cfg(not(all(target_os = "linux", feature = "evdev")))]
+pub fn input_receive(
+    _path: &str,
+    _contacts: &InputContacts,
+    _grab: bool,
+    _settings: KeyerSettings,
+    _key: Box<dyn Key>,
+    _decoder: LiveDecoder,
+) -> anyhow::Result<()> {
+    anyhow::bail!("The 'evdev' feature is not enabled in this crate build (Linux only)")
+}
+
+#[cfg(test)]
+#[cfg(all(target_os = "linux", feature = "evdev"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_code() {
+        assert_eq!(
+            key_code("KEY_LEFTCTRL").unwrap(),
+            evdev::KeyCode::KEY_LEFTCTRL
+        );
+        assert_eq!(
+            key_code("rightctrl").unwrap(),
+            evdev::KeyCode::KEY_RIGHTCTRL
+        );
+        assert_eq!(key_code("btn_left").unwrap(), evdev::KeyCode::BTN_LEFT);
+        assert_eq!(key_code("57").unwrap(), evdev::KeyCode::KEY_SPACE);
+        assert!(key_code("KEY_NOPE").is_err());
+    }
+}
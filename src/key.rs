@@ -0,0 +1,63 @@
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// An output line that follows the key down/up state of the element
+/// timeline (a GPIO pin, a serial RTS/DTR line, ...)
+pub trait Key: Send {
+    fn set(&mut self, down: bool) -> anyhow::Result<()>;
+}
+
+/// Keys several outputs together (e.g. sidetone and a GPIO pin)
+impl Key for Vec<Box<dyn Key>> {
+    fn set(&mut self, down: bool) -> anyhow::Result<()> {
+        self.iter_mut().try_for_each(|key| key.set(down))
+    }
+}
+
+/// Keys the output following a sequence of (frequency, duration_ms).
+/// The key is down for every tone and up for every gap, and is always
+/// left up at the end.
+pub fn key_morse_code(tones: Vec<(f32, u32)>, key: &mut dyn Key) -> anyhow::Result<()> {
+    // Sleep until each element's deadline, so timing doesn't drift:
+    let mut deadline = Instant::now();
+    for (frequency, duration) in tones {
+        if let Err(e) = key.set(frequency > 0. && duration > 0) {
+            let _ = key.set(false);
+            return Err(e);
+        }
+        deadline += Duration::from_millis(duration.into());
+        sleep(deadline.saturating_duration_since(Instant::now()));
+    }
+    key.set(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::morse::encode_morse;
+
+    struct RecordingKey(Vec<(bool, Instant)>);
+
+    impl Key for RecordingKey {
+        fn set(&mut self, down: bool) -> anyhow::Result<()> {
+            self.0.push((down, Instant::now()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_key_morse_code_follows_timeline() {
+        let mut key = RecordingKey(Vec::new());
+        // "A" is .- : dot, gap, dash, gap
+        key_morse_code(encode_morse("A", 10, 600.0), &mut key).unwrap();
+        let states: Vec<bool> = key.0.iter().map(|(down, _)| *down).collect();
+        assert_eq!(states, vec![true, false, true, false, false]);
+        // Elements follow absolute deadlines, so the end of the dash is
+        // at least dot + gap + dash after the start:
+        let elapsed = key.0[3].1.duration_since(key.0[0].1);
+        assert!(
+            elapsed >= Duration::from_millis(49),
+            "dash ended after {elapsed:?}"
+        );
+    }
+}
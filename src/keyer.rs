@@ -0,0 +1,364 @@
+//! An electronic keyer: turns the dit and dah contacts of a paddle
+//! into timed elements. The engine only deals with timestamps, so the
+//! contacts can come from GPIO pins, keyboard keys or a USB adapter,
+//! and the output can be any `Key` (sidetone, GPIO pin, serial line).
+use crate::key::Key;
+use anyhow::bail;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// How the keyer responds to the paddles
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyerMode {
+    /// Squeezing alternates dits and dahs, stopping as soon as the
+    /// paddles are released
+    IambicA,
+    /// Like Iambic A, but releasing a squeeze sends one more
+    /// alternate element
+    #[default]
+    IambicB,
+    /// Squeezing repeats the element of the last paddle pressed
+    Ultimatic,
+    /// Semi-automatic: the dit paddle repeats dits, the dah paddle is a
+    /// straight key
+    Bug,
+}
+
+impl FromStr for KeyerMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "a" | "iambic-a" => KeyerMode::IambicA,
+            "b" | "iambic-b" => KeyerMode::IambicB,
+            "ultimatic" => KeyerMode::Ultimatic,
+            "bug" => KeyerMode::Bug,
+            _ => bail!("unknown keyer mode `{s}` (expected iambic-a, iambic-b, ultimatic or bug)"),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Element {
+    Dit,
+    Dah,
+}
+
+impl Element {
+    fn opposite(self) -> Self {
+        match self {
+            Element::Dit => Element::Dah,
+            Element::Dah => Element::Dit,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct KeyerSettings {
+    pub mode: KeyerMode,
+    pub dot_duration: u32, // Dot length in milliseconds (sets the speed)
+    pub weight: u32,       // Key down share of an element in % [25..75], 50 is standard
+    pub memory: bool,      // Remember the opposite paddle tapped during an element
+}
+
+impl Default for KeyerSettings {
+    fn default() -> Self {
+        Self {
+            mode: KeyerMode::default(),
+            dot_duration: 60,
+            weight: 50,
+            memory: true,
+        }
+    }
+}
+
+/// A change of the keyer's output at a point in time (relative to any
+/// fixed start, as long as all inputs use the same one)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub at: Duration,
+    pub down: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Idle,
+    Sending { element: Element, started: Duration },
+}
+
+/// The keyer state machine. Feed it the paddle contacts with `update`
+/// and it returns when the key goes down and up.
+pub struct Keyer {
+    settings: KeyerSettings,
+    state: State,
+    dit: bool, // Paddle contacts
+    dah: bool,
+    dit_memory: bool,
+    dah_memory: bool,
+    last_pressed: Element, // For Ultimatic
+    automatic_down: bool,  // Key state of the automatic elements
+    down: bool,            // Key state of the output
+}
+
+impl Keyer {
+    pub fn new(settings: KeyerSettings) -> Self {
+        Self {
+            settings: KeyerSettings {
+                weight: settings.weight.clamp(25, 75),
+                dot_duration: settings.dot_duration.max(1),
+                ..settings
+            },
+            state: State::Idle,
+            dit: false,
+            dah: false,
+            dit_memory: false,
+            dah_memory: false,
+            last_pressed: Element::Dit,
+            automatic_down: false,
+            down: false,
+        }
+    }
+
+    /// Key down time and total length (including the following element
+    /// gap) of an element. The weight moves time between the two,
+    /// without changing the speed.
+    fn timing(&self, element: Element) -> (Duration, Duration) {
+        let dot = self.settings.dot_duration as u64 * 1000;
+        let length = match element {
+            Element::Dit => dot,
+            Element::Dah => dot * 3,
+        };
+        let down = length + dot * self.settings.weight as u64 / 50 - dot;
+        (
+            Duration::from_micros(down),
+            Duration::from_micros(length + dot),
+        )
+    }
+
+    /// When the output will change next without any paddle input.
+    pub fn next_deadline(&self) -> Option<Duration> {
+        match self.state {
+            State::Idle => None,
+            State::Sending { element, started } => {
+                let (down, length) = self.timing(element);
+                Some(started + if self.automatic_down { down } else { length })
+            }
+        }
+    }
+
+    fn output(&mut self, at: Duration, events: &mut Vec<KeyEvent>) {
+        let straight = self.settings.mode == KeyerMode::Bug && self.dah;
+        let down = self.automatic_down || straight;
+        if down != self.down {
+            self.down = down;
+            events.push(KeyEvent { at, down });
+        }
+    }
+
+    fn memory(&mut self, element: Element) -> &mut bool {
+        match element {
+            Element::Dit => &mut self.dit_memory,
+            Element::Dah => &mut self.dah_memory,
+        }
+    }
+
+    fn pressed(&self, element: Element) -> bool {
+        match element {
+            Element::Dit => self.dit,
+            Element::Dah => self.dah,
+        }
+    }
+
+    /// Chooses the element to send after `last` (or from idle).
+    fn next_element(&self, last: Option<Element>) -> Option<Element> {
+        if self.settings.mode == KeyerMode::Bug {
+            return self.dit.then_some(Element::Dit);
+        }
+        let dit = self.dit || self.dit_memory;
+        let dah = self.dah || self.dah_memory;
+        match (dit, dah) {
+            (true, true) => Some(match (self.settings.mode, last) {
+                (KeyerMode::Ultimatic, _) | (_, None) => self.last_pressed,
+                (_, Some(last)) => last.opposite(),
+            }),
+            (true, false) => Some(Element::Dit),
+            (false, true) => Some(Element::Dah),
+            (false, false) => None,
+        }
+    }
+
+    fn start(&mut self, element: Element, at: Duration, events: &mut Vec<KeyEvent>) {
+        *self.memory(element) = false;
+        // Iambic B remembers a squeeze held from the start of the element:
+        if self.settings.mode == KeyerMode::IambicB && self.pressed(element.opposite()) {
+            *self.memory(element.opposite()) = true;
+        }
+        self.state = State::Sending {
+            element,
+            started: at,
+        };
+        self.automatic_down = true;
+        self.output(at, events);
+    }
+
+    /// Runs the element timeline up to `now`.
+    fn advance(&mut self, now: Duration, events: &mut Vec<KeyEvent>) {
+        while let State::Sending { element, started } = self.state {
+            let (down, length) = self.timing(element);
+            if self.automatic_down && started + down <= now {
+                self.automatic_down = false;
+                self.output(started + down, events);
+            }
+            if started + length > now {
+                break;
+            }
+            match self.next_element(Some(element)) {
+                Some(next) => self.start(next, started + length, events),
+                None => self.state = State::Idle,
+            }
+        }
+    }
+
+    /// Sets the paddle contacts at `now`, and returns the key changes
+    /// since the last update, at their exact times. `now` must never
+    /// go backwards.
+    pub fn update(&mut self, now: Duration, dit: bool, dah: bool) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+        self.advance(now, &mut events);
+
+        for (element, pressed) in [(Element::Dit, dit), (Element::Dah, dah)] {
+            if pressed && !self.pressed(element) {
+                self.last_pressed = element;
+                // Remember taps of the other paddle during an element:
+                let sending = match self.state {
+                    State::Sending { element, .. } => Some(element),
+                    State::Idle => None,
+                };
+                let latch = match self.settings.mode {
+                    KeyerMode::IambicB => true,
+                    KeyerMode::Bug => false,
+                    _ => self.settings.memory,
+                };
+                if latch && sending == Some(element.opposite()) {
+                    *self.memory(element) = true;
+                }
+            }
+        }
+        self.dit = dit;
+        self.dah = dah;
+
+        if let (State::Idle, Some(element)) = (self.state, self.next_element(None)) {
+            self.start(element, now, &mut events);
+        }
+        self.output(now, &mut events);
+        events
+    }
+}
+
+/// Runs the keyer, reading the paddle contacts (dit, dah) with
+/// `paddles` every millisecond and keying `key`, until `abort` is set.
+/// `on_update` is called after every poll with the time since the
+/// start and the key changes it made.
+#[cfg_attr(not(feature = "gpio"), allow(dead_code))] // Only used by GPIO paddles so far
+pub fn run(
+    keyer: &mut Keyer,
+    mut paddles: impl FnMut() -> anyhow::Result<(bool, bool)>,
+    key: &mut dyn Key,
+    abort: &AtomicBool,
+    mut on_update: impl FnMut(Duration, &[KeyEvent]) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let poll = Duration::from_millis(1);
+    while !abort.load(Ordering::SeqCst) {
+        let (dit, dah) = paddles()?;
+        let now = start.elapsed();
+        let events = keyer.update(now, dit, dah);
+        for event in &events {
+            key.set(event.down)?;
+        }
+        on_update(now, &events)?;
+        // Wake up early for the next element edge, so it isn't late:
+        let wake = match keyer.next_deadline() {
+            Some(deadline) => (start + deadline).min(Instant::now() + poll),
+            None => Instant::now() + poll,
+        };
+        sleep(wake.saturating_duration_since(Instant::now()));
+    }
+    key.set(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds paddle changes (ms, dit, dah) to a keyer at 60ms per dot,
+    /// and returns what it sent as dots and dashes.
+    fn keyed(mode: KeyerMode, inputs: &[(u64, bool, bool)], until: u64) -> String {
+        let mut keyer = Keyer::new(KeyerSettings {
+            mode,
+            ..Default::default()
+        });
+        let mut events = Vec::new();
+        for &(at, dit, dah) in inputs {
+            events.extend(keyer.update(Duration::from_millis(at), dit, dah));
+        }
+        let (dit, dah) = inputs.last().map(|&(_, dit, dah)| (dit, dah)).unwrap();
+        events.extend(keyer.update(Duration::from_millis(until), dit, dah));
+        events
+            .chunks(2)
+            .map(|pair| match pair[1].at - pair[0].at {
+                length if length >= Duration::from_millis(120) => '-',
+                _ => '.',
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_keyer_modes() {
+        // Squeeze dit first, release both during the second dit:
+        let squeeze = [(0, true, false), (10, true, true), (400, false, false)];
+        assert_eq!(keyed(KeyerMode::IambicA, &squeeze, 2000), ".-.");
+        assert_eq!(keyed(KeyerMode::IambicB, &squeeze, 2000), ".-.-");
+        assert_eq!(keyed(KeyerMode::Ultimatic, &squeeze, 2000), ".--");
+
+        // Dah tapped during a dit is remembered:
+        let tap = [(0, true, false), (20, true, true), (30, false, false)];
+        assert_eq!(keyed(KeyerMode::IambicA, &tap, 2000), ".-");
+
+        // Bug: held dits repeat, the dah paddle keys straight:
+        let bug = [
+            (0, true, false),
+            (250, false, false),
+            (400, false, true),
+            (700, false, false),
+        ];
+        assert_eq!(keyed(KeyerMode::Bug, &bug, 800), "...-");
+    }
+
+    #[test]
+    fn test_keyer_weight() {
+        let mut keyer = Keyer::new(KeyerSettings {
+            weight: 60,
+            ..Default::default()
+        });
+        let down = keyer.update(Duration::ZERO, true, false);
+        let up = keyer.update(Duration::from_millis(100), false, false);
+        assert_eq!(
+            down[0],
+            KeyEvent {
+                at: Duration::ZERO,
+                down: true
+            }
+        );
+        assert_eq!(
+            up[0],
+            KeyEvent {
+                at: Duration::from_millis(72),
+                down: false
+            }
+        );
+        assert_eq!(keyer.next_deadline(), Some(Duration::from_millis(120)));
+    }
+}
@@ -0,0 +1,223 @@
+use crate::morse::{encode_morse, get_decoder, MorsePlayer};
+use crate::pipewire;
+use crate::prelude::*;
+use std::time::{Duration, Instant};
+
+/// The known pattern played during the loopback test
+pub const LOOPBACK_PATTERN: &str = "VVV PARIS 73";
+
+/// Envelope resolution used to find the key down/up edges
+const WINDOW_MS: u32 = 5;
+
+/// Results of analyzing a captured loopback recording
+#[derive(Debug)]
+pub struct LoopbackReport {
+    pub latency: Option<Duration>, // From starting playback to hearing the first tone
+    pub pitch: Option<f32>,        // Detected tone frequency in Hz
+    pub decoded: String,
+    pub accuracy: f64, // [0.0..1.0]
+}
+
+/// Plays the loopback pattern through the player while capturing the
+/// system audio monitor, then analyzes the recording.
+pub fn run(
+    player: &MorsePlayer,
+    dot_duration: u32,
+    tone_freq: f32,
+) -> anyhow::Result<LoopbackReport> {
+    let pattern_duration: u32 = encode_morse(LOOPBACK_PATTERN, dot_duration, tone_freq)
+        .iter()
+        .map(|(_, duration)| duration)
+        .sum();
+    // Leave room for the capture to start and for the output latency:
+    let capture_duration = Duration::from_millis(pattern_duration as u64 + 3000);
+
+    let (ready_sender, ready_receiver) = std::sync::mpsc::channel();
+    let capture = std::thread::spawn(move || pipewire::capture(capture_duration, ready_sender));
+    if ready_receiver
+        .recv_timeout(Duration::from_millis(2000))
+        .is_err()
+    {
+        return match capture.join() {
+            Ok(Err(e)) => Err(e),
+            _ => anyhow::bail!("Timed out waiting for the audio monitor to start capturing."),
+        };
+    }
+
+    info!("Playing loopback pattern: {LOOPBACK_PATTERN}");
+    let playback_started = Instant::now();
+    player.play(LOOPBACK_PATTERN, dot_duration, tone_freq);
+
+    let capture = capture
+        .join()
+        .map_err(|_| anyhow::anyhow!("audio capture thread panicked"))??;
+    let started = capture
+        .started
+        .ok_or_else(|| anyhow::anyhow!("No audio was captured from the monitor."))?;
+    Ok(analyze(
+        &capture.samples,
+        capture.sample_rate,
+        playback_started.saturating_duration_since(started),
+        LOOPBACK_PATTERN,
+        dot_duration,
+    ))
+}
+
+/// Analyzes a mono recording of `expected` played as morse code.
+///
+/// `playback_offset` is the time between the start of the recording
+/// and the start of playback.
+pub fn analyze(
+    samples: &[f32],
+    sample_rate: u32,
+    playback_offset: Duration,
+    expected: &str,
+    dot_duration: u32,
+) -> LoopbackReport {
+    let window = (sample_rate * WINDOW_MS / 1000).max(1) as usize;
+    let envelope: Vec<f32> = samples
+        .chunks(window)
+        .map(|chunk| chunk.iter().fold(0.0_f32, |peak, s| peak.max(s.abs())))
+        .collect();
+    let peak = envelope.iter().fold(0.0_f32, |peak, e| peak.max(*e));
+    if peak < 0.01 {
+        return LoopbackReport {
+            latency: None,
+            pitch: None,
+            decoded: String::new(),
+            accuracy: 0.0,
+        };
+    }
+    let keyed: Vec<bool> = envelope.iter().map(|e| *e > peak / 2.0).collect();
+
+    // Latency is the time of the first keyed window after playback started:
+    let latency = keyed.iter().position(|k| *k).and_then(|first| {
+        Duration::from_millis((first as u32 * WINDOW_MS).into()).checked_sub(playback_offset)
+    });
+
+    // Pitch from the spacing of the zero crossings within the keyed windows:
+    let (half_periods, span) = samples
+        .chunks(window)
+        .zip(&keyed)
+        .filter(|(_, keyed)| **keyed)
+        .fold((0, 0), |(half_periods, span), (chunk, _)| {
+            let crossings: Vec<usize> = chunk
+                .windows(2)
+                .enumerate()
+                .filter(|(_, pair)| (pair[0] < 0.0) != (pair[1] < 0.0))
+                .map(|(i, _)| i)
+                .collect();
+            match (crossings.first(), crossings.last()) {
+                (Some(first), Some(last)) if last > first => {
+                    (half_periods + crossings.len() - 1, span + last - first)
+                }
+                _ => (half_periods, span),
+            }
+        });
+    let pitch = (span > 0).then(|| half_periods as f32 * sample_rate as f32 / span as f32 / 2.0);
+
+    // Decode the key down/up runs:
+    let mut decoder = get_decoder(dot_duration);
+    let mut runs: Vec<(bool, u32)> = Vec::new();
+    for state in keyed.iter().skip_while(|k| !**k) {
+        match runs.last_mut() {
+            Some((last, length)) if last == state => *length += WINDOW_MS,
+            _ => runs.push((*state, WINDOW_MS)),
+        }
+    }
+    for (state, duration) in runs {
+        decoder.signal_event(duration.min(u16::MAX as u32) as u16, state);
+    }
+    decoder.signal_event_end(false);
+    let decoded = normalize(decoder.message());
+
+    let expected = normalize(expected);
+    let distance = edit_distance(&decoded, &expected);
+    let accuracy = 1.0 - (distance as f64 / expected.chars().count().max(1) as f64).min(1.0);
+
+    LoopbackReport {
+        latency,
+        pitch,
+        decoded,
+        accuracy,
+    }
+}
+
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_uppercase()
+}
+
+/// Levenshtein distance between two strings (in characters)
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+pub fn print_report(report: &LoopbackReport, tone_freq: f32) {
+    println!("\nLoopback results:");
+    match report.latency {
+        Some(latency) => println!("Round-trip latency: {}ms", latency.as_millis()),
+        None => println!("Round-trip latency: no tone detected"),
+    }
+    match report.pitch {
+        Some(pitch) => println!("Detected pitch: {pitch:.0}Hz (expected {tone_freq:.0}Hz)"),
+        None => println!("Detected pitch: no tone detected"),
+    }
+    println!("Expected: {LOOPBACK_PATTERN}");
+    println!("Decoded:  {}", report.decoded);
+    println!("Decode accuracy: {:.0}%", report.accuracy * 100.0);
+    if let Some(latency) = report.latency {
+        println!("\nTo exclude this audio latency from your FECR quiz reaction times:");
+        println!(
+            "\n   code-smore fecr-quiz --output-latency {}",
+            latency.as_millis()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::morse::{render_tones, SAMPLE_RATE};
+
+    #[test]
+    fn test_analyze_synthetic_loopback() {
+        let offset = Duration::from_millis(200);
+        let latency = Duration::from_millis(150);
+        let mut samples =
+            vec![0.0; (SAMPLE_RATE as u128 * (offset + latency).as_millis() / 1000) as usize];
+        samples.extend(render_tones(encode_morse(LOOPBACK_PATTERN, 60, 600.0), 0.5));
+        samples.extend(vec![0.0; SAMPLE_RATE as usize]);
+
+        let report = analyze(&samples, SAMPLE_RATE, offset, LOOPBACK_PATTERN, 60);
+        let measured = report.latency.expect("expected a latency").as_millis() as i64;
+        assert!(
+            (measured - 150).abs() <= WINDOW_MS as i64,
+            "latency {measured}ms"
+        );
+        let pitch = report.pitch.expect("expected a pitch");
+        assert!((pitch - 600.0).abs() < 10.0, "pitch {pitch}Hz");
+        assert_eq!(report.decoded, LOOPBACK_PATTERN);
+        assert_eq!(report.accuracy, 1.0);
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("PARIS", "PARIS"), 0);
+        assert_eq!(edit_distance("PAKIS", "PARIS"), 1);
+        assert_eq!(edit_distance("PRIS", "PARIS"), 1);
+        assert_eq!(edit_distance("", "73"), 2);
+    }
+}
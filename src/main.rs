@@ -2,19 +2,37 @@ use clap_complete::shells::Shell;
 
 mod cli;
 mod credits;
+mod cwdaemon;
+mod cwlog;
+mod decode;
 mod fecr_quiz;
 mod filter;
+mod fist;
 mod gpio;
+mod input;
+mod key;
+mod keyer;
+mod loopback;
 mod message;
+mod midi;
+mod mixer;
 mod morse;
 mod pipewire;
+mod practice_key;
 mod prelude;
+mod ptt;
+mod rigctld;
+mod send_practice;
+mod serial;
+mod shutdown;
 mod term;
+mod timings;
+mod winkeyer;
+mod winkeyer_emulator;
 
 use is_terminal::IsTerminal;
 use prelude::*;
 use std::io::BufRead;
-use std::u8;
 
 use crate::pipewire::ensure_pipewire;
 
@@ -40,6 +58,11 @@ fn main() {
         .format_timestamp(None)
         .init();
     debug!("logging initialized.");
+    shutdown::init(std::time::Duration::from_millis(
+        *matches
+            .get_one::<u64>("max-key-down")
+            .expect("Missing --max-key-down arg default"),
+    ));
 
     // Print help if no subcommand is given:
     if matches.subcommand_name().is_none() {
@@ -58,20 +81,337 @@ fn main() {
     let sound = *matches
         .get_one::<bool>("sound")
         .expect("Missing --sound arg default");
+    let output_device = matches
+        .get_one::<String>("output-device")
+        .map(|s| s.as_str());
+    let volume: f32 = *matches
+        .get_one::<f32>("volume")
+        .expect("Missing --volume arg default");
+    let pipewire_node = matches
+        .get_one::<String>("pipewire-node")
+        .map(|s| s.as_str());
+    let ms = |name: &str| {
+        std::time::Duration::from_millis(
+            *matches
+                .get_one::<u64>(name)
+                .unwrap_or_else(|| panic!("Missing --{name} arg default")),
+        )
+    };
+    let ptt_timing = ptt::PttTiming {
+        lead: ms("ptt-lead"),
+        tail: ms("ptt-tail"),
+        hang: ms("ptt-hang"),
+    };
+    let rigctld = matches.get_one::<String>("rigctld").map(|s| s.as_str());
+    let rig_keyer = matches.get_flag("rig-keyer");
+    let open_rig = || {
+        rigctld.map(|address| match rigctld::Rigctld::connect(address) {
+            Ok(rig) => rig,
+            Err(e) => {
+                error!("{e:#}");
+                shutdown::exit(1);
+            }
+        })
+    };
     let gpio = matches.get_one::<u8>("gpio").is_some();
+    let gpio_chip = matches.get_one::<String>("gpio-chip").cloned();
+    let gpio_output = gpio::GpioOutput {
+        chip: gpio_chip.clone(),
+        active_low: matches
+            .get_one::<String>("gpio-output-active")
+            .is_some_and(|level| level == "low"),
+    };
+    let gpio_ptt = matches.get_one::<u8>("gpio-ptt").copied();
+    let gpio_buzzer = matches.get_one::<u8>("gpio-buzzer").copied();
+    // PTT switches the transmitter on a pin of its own:
+    let gpio_pins = [
+        ("--gpio", matches.get_one::<u8>("gpio").copied()),
+        ("--gpio-buzzer", gpio_buzzer),
+    ];
+    for (arg, pin) in gpio_pins {
+        if gpio_ptt.is_some() && pin == gpio_ptt {
+            eprintln!("Error: '--gpio-ptt' cannot use the same pin as '{arg}'.");
+            shutdown::exit(1);
+        }
+    }
+    // Live keying (paddles, keyboard) has no sidetone with --text:
+    let text_only = text && !sound;
+    let open_output = |silent: bool| {
+        let player = match (pipewire_node, gpio_buzzer) {
+            (Some(node_name), _) => morse::MorsePlayer::open_pipewire(node_name, volume),
+            (None, Some(pin_number)) => {
+                morse::MorsePlayer::open_buzzer(pin_number, &gpio_output, tone_freq)
+            }
+            (None, None) if silent => Ok(morse::MorsePlayer::silent(volume)),
+            (None, None) => morse::MorsePlayer::open(output_device, volume),
+        };
+        let ptt = match (gpio_ptt, rts_port, rigctld) {
+            (Some(pin_number), _, _) => Some(
+                gpio::GpioKey::new(pin_number, &gpio_output)
+                    .map(|pin| shutdown::guard(&format!("GPIO PTT pin {pin_number}"), pin, false)),
+            ),
+            (None, Some(port_name), _) => Some(
+                serial::SerialLineKey::open(port_name, serial::SerialLine::Rts)
+                    .map(|rts| shutdown::guard(&format!("RTS PTT on {port_name}"), rts, false)),
+            ),
+            // The rig's own keyer switches the transmitter by itself:
+            (None, None, Some(address)) if !rig_keyer => Some(
+                rigctld::Rigctld::connect(address)
+                    .map(|rig| shutdown::guard(&format!("rigctld PTT at {address}"), rig, false)),
+            ),
+            _ => None,
+        }
+        .map(|ptt| ptt.map(|key| ptt::Ptt::new(Box::new(key), ptt_timing)));
+        match (player, ptt.transpose()) {
+            (Ok(mut player), Ok(ptt)) => {
+                player.set_ptt(ptt);
+                player
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                error!("{e:#}");
+                shutdown::exit(1);
+            }
+        }
+    };
+    let midi_out = matches.get_one::<String>("midi-out").map(|s| s.as_str());
+    let open_player = || open_output(false);
+    let open_live_player = || open_output(text_only);
+    let key_line = matches.get_one::<String>("key-line").map(|s| {
+        s.parse::<serial::SerialLine>()
+            .expect("Invalid --key-line value")
+    });
+    let key_port = matches
+        .get_one::<String>("key-port")
+        .map(|s| s.as_str())
+        .or(rts_port);
+    match (key_line, key_port) {
+        (Some(_), None) => {
+            eprintln!("Error: '--key-line' requires a serial port, set '--key-port' or '--rts'.");
+            shutdown::exit(1);
+        }
+        (Some(serial::SerialLine::Rts), Some(port)) if Some(port) == rts_port => {
+            eprintln!(
+                "Error: RTS cannot be used for both PTT ('--rts') and keying ('--key-line rts')."
+            );
+            shutdown::exit(1);
+        }
+        _ => {}
+    }
     let gpio_pin: u8 = matches.get_one::<u8>("gpio").copied().unwrap_or(u8::MAX);
+    let gpio_input = gpio::GpioInput {
+        chip: gpio_output.chip.clone(),
+        pull: matches
+            .get_one::<String>("gpio-pull")
+            .expect("Missing --gpio-pull arg default")
+            .parse()
+            .expect("Invalid --gpio-pull value"),
+        active_high: matches
+            .get_one::<String>("gpio-input-active")
+            .is_some_and(|level| level == "high"),
+        debounce: std::time::Duration::from_millis(
+            *matches
+                .get_one::<u64>("gpio-debounce")
+                .expect("Missing --gpio-debounce arg default"),
+        ),
+    };
+    // The GPIO pin or serial key line, or else sound:
+    let open_cw_output = || -> anyhow::Result<morse::CwOutput> {
+        if gpio {
+            let pin = gpio::GpioKey::new(gpio_pin, &gpio_output)?;
+            let pin = shutdown::guard(&format!("GPIO pin {gpio_pin}"), pin, true);
+            Ok(morse::CwOutput::Key(Box::new(pin)))
+        } else if let Some(line) = key_line {
+            let port_name = key_port.expect("Missing --key-port");
+            let key = serial::SerialLineKey::open(port_name, line)?;
+            let key = shutdown::guard(&format!("{line} key line on {port_name}"), key, true);
+            Ok(morse::CwOutput::Key(Box::new(key)))
+        } else if let Some(port_name) = midi_out {
+            let key = midi::MidiKey::open(port_name, tone_freq, volume)?;
+            let key = shutdown::guard(&format!("MIDI port {port_name}"), key, true);
+            Ok(morse::CwOutput::Key(Box::new(key)))
+        } else {
+            Ok(morse::CwOutput::Sound)
+        }
+    };
 
     // Calculate dot duration from wpm if not provided:
     let dot_duration = match (matches.get_one::<u32>("dot"), matches.get_one::<u32>("wpm")) {
         (Some(_), Some(_)) => {
             eprintln!("Error: '--dot' and '--wpm' cannot be used together.");
-            std::process::exit(1);
+            shutdown::exit(1);
         }
         (Some(&dot), None) => dot,
         (None, Some(&wpm)) => morse::wpm_to_dot_length(wpm),
         (None, None) => 60, // Default dot duration @ 20WPM
     };
 
+    let keyer_settings = keyer::KeyerSettings {
+        mode: matches
+            .get_one::<String>("keyer-mode")
+            .expect("Missing --keyer-mode arg default")
+            .parse()
+            .expect("Invalid --keyer-mode value"),
+        dot_duration,
+        weight: *matches
+            .get_one::<u32>("keyer-weight")
+            .expect("Missing --keyer-weight arg default"),
+        memory: !matches.get_flag("no-keyer-memory"),
+    };
+
+    // The keys live keying (paddles, keyboard) goes to: sidetone, and
+    // the outputs (with `outputs`). With PTT, the outputs follow the
+    // lead time later, so PTT is switched before they go down
+    let open_live_keys = |player: &mut morse::MorsePlayer,
+                          mut outputs: Vec<Box<dyn key::Key>>|
+     -> anyhow::Result<Vec<Box<dyn key::Key>>> {
+        let mut keys: Vec<Box<dyn key::Key>> = Vec::new();
+        if !text_only {
+            match player.sidetone(tone_freq) {
+                Ok(sidetone) => keys.push(Box::new(sidetone)),
+                Err(e) => warn!("No sidetone: {e:#}"),
+            }
+        }
+        if let morse::CwOutput::Key(key) = open_cw_output()? {
+            outputs.push(key);
+        }
+        match player.take_ptt() {
+            Some(ptt) => keys.push(Box::new(ptt::PttKey::new(ptt, Box::new(outputs)))),
+            None => keys.extend(outputs),
+        }
+        Ok(keys)
+    };
+    // Recording of what is received (--record), and the decoder of live
+    // keying that records it:
+    let open_recorder = |sub_matches: &clap::ArgMatches, source: &str| {
+        sub_matches
+            .get_one::<String>("record")
+            .map(|path| cwlog::Recorder::create(path, source, dot_duration))
+            .transpose()
+    };
+    let open_decoder = |sub_matches: &clap::ArgMatches,
+                        source: &str,
+                        rig: Option<rigctld::Rigctld>|
+     -> anyhow::Result<decode::LiveDecoder> {
+        let recorder = open_recorder(sub_matches, source)?;
+        let output_morse = sub_matches.get_flag("morse");
+        Ok(decode::LiveDecoder::new(dot_duration, output_morse, rig)
+            .record(recorder)
+            .analyze(sub_matches.get_flag("analyze")))
+    };
+
+    // Keying from a straight key or a paddle (on GPIO, an input device or
+    // MIDI), with sidetone and any outputs, for `receive` and
+    // `send-practice`. None without one:
+    let receive_keyed =
+        |sub_matches: &clap::ArgMatches,
+         open_decoder: &mut dyn FnMut(&str) -> anyhow::Result<decode::LiveDecoder>|
+         -> Option<anyhow::Result<()>> {
+            let paddle = sub_matches
+                .get_one::<u8>("gpio-dit")
+                .zip(sub_matches.get_one::<u8>("gpio-dah"));
+            let result = if let Some((&dit_pin, &dah_pin)) = paddle {
+                // Key from a paddle on GPIO:
+                let mut player = open_live_player();
+                let key_pin = |pin_number: u8| -> anyhow::Result<Box<dyn key::Key>> {
+                    let pin = gpio::GpioKey::new(pin_number, &gpio_output)?;
+                    let pin = shutdown::guard(&format!("GPIO pin {pin_number}"), pin, true);
+                    Ok(Box::new(pin))
+                };
+                let outputs = sub_matches
+                    .get_one::<u8>("gpio-key")
+                    .map(|&pin| key_pin(pin));
+                let outputs = outputs.into_iter().collect::<anyhow::Result<Vec<_>>>();
+                outputs
+                    .and_then(|outputs| open_live_keys(&mut player, outputs))
+                    .and_then(|keys| {
+                        let source =
+                            format!("GPIO paddle on pins {dit_pin} (dit) and {dah_pin} (dah)");
+                        gpio::gpio_paddle_receive(
+                            dit_pin,
+                            dah_pin,
+                            gpio_input.clone(),
+                            keyer_settings,
+                            Box::new(keys),
+                            open_decoder(&source)?,
+                        )
+                    })
+            } else if let Some(path) = sub_matches.get_one::<String>("input-device") {
+                // Key from a key or paddle on a Linux input device
+                let contacts = match sub_matches.get_one::<String>("input-key") {
+                    None if !sub_matches.contains_id("input-dit") => input::InputContacts {
+                        // The Ctrl keys of USB paddle adapters (VBand, Vail):
+                        dit: Some("KEY_LEFTCTRL".to_string()),
+                        dah: Some("KEY_RIGHTCTRL".to_string()),
+                        ..Default::default()
+                    },
+                    straight => input::InputContacts {
+                        straight: straight.cloned(),
+                        dit: sub_matches.get_one::<String>("input-dit").cloned(),
+                        dah: sub_matches.get_one::<String>("input-dah").cloned(),
+                    },
+                };
+                let mut player = open_live_player();
+                open_live_keys(&mut player, Vec::new()).and_then(|keys| {
+                    let source = format!("input device {path}");
+                    input::input_receive(
+                        path,
+                        &contacts,
+                        sub_matches.get_flag("input-grab"),
+                        keyer_settings,
+                        Box::new(keys),
+                        open_decoder(&source)?,
+                    )
+                })
+            } else if let Some(port_name) = sub_matches.get_one::<String>("midi-in") {
+                // Key from a MIDI keyer adapter
+                let note = |name: &str| {
+                    *sub_matches
+                        .get_one::<u8>(name)
+                        .unwrap_or_else(|| panic!("Missing --{name} arg default"))
+                };
+                let notes = midi::MidiNotes {
+                    straight: note("midi-key"),
+                    dit: note("midi-dit"),
+                    dah: note("midi-dah"),
+                };
+                let mut player = open_live_player();
+                open_live_keys(&mut player, Vec::new()).and_then(|keys| {
+                    let source = format!("MIDI port {port_name}");
+                    midi::midi_receive(
+                        port_name,
+                        notes,
+                        keyer_settings,
+                        Box::new(keys),
+                        open_decoder(&source)?,
+                    )
+                })
+            } else if gpio {
+                // Receive from GPIO, with the buzzer (if any) as sidetone
+                let player = gpio_buzzer.map(|_| open_player());
+                player
+                    .as_ref()
+                    .map(|player| player.sidetone(tone_freq))
+                    .transpose()
+                    .and_then(|sidetone| {
+                        let sidetone: Box<dyn key::Key> = match sidetone {
+                            Some(sidetone) => Box::new(sidetone),
+                            None => Box::new(Vec::<Box<dyn key::Key>>::new()),
+                        };
+                        let source = format!("GPIO pin {gpio_pin}");
+                        gpio::gpio_receive(
+                            dot_duration,
+                            gpio_pin,
+                            gpio_input.clone(),
+                            sidetone,
+                            open_decoder(&source)?,
+                        )
+                    })
+            } else {
+                return None;
+            };
+            Some(result)
+        };
+
     // Handle the subcommands:
     let exit_code = match matches.subcommand() {
         Some(("fecr-quiz", sub_matches)) => {
@@ -90,7 +430,11 @@ fn main() {
             let baseline = sub_matches
                 .get_one::<u32>("baseline")
                 .expect("Missing --baseline arg default");
+            let output_latency = sub_matches
+                .get_one::<u32>("output-latency")
+                .expect("Missing --output-latency arg default");
             fecr_quiz::start_quiz(
+                &open_player(),
                 *trials,
                 char_set,
                 dot_duration,
@@ -99,24 +443,115 @@ fn main() {
                 *randomize,
                 *calibration_mode,
                 *baseline,
-                rts_port,
+                *output_latency,
             );
             0
         }
-        Some(("test-sound", _sub_matches)) => {
-            let player = morse::MorsePlayer::new();
-            let message = "If sound is working, you should hear this test message now.";
-            println!("{}", message);
-            println!("{}", text_to_morse(message));
-            player.play(message, dot_duration, tone_freq, rts_port);
-            0
+        Some(("test-sound", sub_matches)) => {
+            if sub_matches.get_flag("list-devices") {
+                match morse::list_output_devices() {
+                    Ok(devices) => {
+                        println!("Available audio output devices:");
+                        if devices.is_empty() {
+                            println!("  (none found)");
+                        }
+                        for device in devices {
+                            println!("  {device}");
+                        }
+                        0
+                    }
+                    Err(e) => {
+                        error!("{e:#}");
+                        1
+                    }
+                }
+            } else if sub_matches.get_flag("loopback") {
+                if !cfg!(target_os = "linux") {
+                    error!("Sorry, the loopback test is only supported on Linux right now.");
+                    shutdown::exit(1);
+                }
+                ensure_pipewire();
+                let player = open_player();
+                match loopback::run(&player, dot_duration, tone_freq) {
+                    Ok(report) => {
+                        loopback::print_report(&report, tone_freq);
+                        0
+                    }
+                    Err(e) => {
+                        error!("{e:#}");
+                        1
+                    }
+                }
+            } else {
+                let player = open_player();
+                let message = "If sound is working, you should hear this test message now.";
+                println!("{}", message);
+                println!("{}", text_to_morse(message));
+                player.play(message, dot_duration, tone_freq);
+                0
+            }
         }
         Some(("send", sub_matches)) => {
-            let player = morse::MorsePlayer::new();
+            // No sound is played with --text, or when keying GPIO or MIDI:
+            let player = open_output(text_only || gpio || midi_out.is_some());
             let morse = sub_matches
                 .get_one::<bool>("morse")
                 .expect("Missing --morse arg default");
 
+            // A GPIO pin, serial key line or MIDI port, keyed for the whole
+            // send instead of playing sound:
+            let mut key_line = match (gpio || key_line.is_some() || midi_out.is_some())
+                .then(open_cw_output)
+                .transpose()
+            {
+                Ok(Some(morse::CwOutput::Key(key))) => Some(key),
+                Ok(_) => None,
+                Err(e) => {
+                    error!("{e:#}");
+                    shutdown::exit(1);
+                }
+            };
+            // The buzzer sounds as a sidetone while keying GPIO:
+            if let (true, Some(_), Some(key)) = (gpio, gpio_buzzer, key_line.take()) {
+                key_line = match player.sidetone(tone_freq) {
+                    Ok(sidetone) => {
+                        Some(Box::new(vec![key, Box::new(sidetone) as Box<dyn key::Key>]))
+                    }
+                    Err(e) => {
+                        error!("{e:#}");
+                        shutdown::exit(1);
+                    }
+                };
+            }
+            let midi_file = sub_matches.get_one::<String>("midi-file");
+            let mut midi_tones = Vec::new();
+            // Only connect to the rig to send through its own keyer:
+            let mut rig_keyer = rig_keyer.then(open_rig).flatten().map(|mut rig| {
+                if let Err(e) = rig.set_keyer_speed(morse::dot_length_to_wpm(dot_duration)) {
+                    warn!("Could not set the rig keyer speed: {e:#}");
+                }
+                rig
+            });
+            let mut winkeyer = match sub_matches.get_one::<String>("winkeyer") {
+                Some(port_name) => {
+                    let opened = winkeyer::WinKeyer::open(port_name).and_then(|mut keyer| {
+                        keyer.set_speed(morse::dot_length_to_wpm(dot_duration))?;
+                        keyer.set_sidetone(tone_freq)?;
+                        Ok(keyer)
+                    });
+                    match opened.and_then(|keyer| Ok((keyer.abort_handle()?, keyer))) {
+                        Ok((abort, keyer)) => {
+                            Some((shutdown::guard("WinKeyer", abort, false), keyer))
+                        }
+                        Err(e) => {
+                            error!("{e:#}");
+                            shutdown::exit(1);
+                        }
+                    }
+                }
+                None => None,
+            };
+
             let stdin = std::io::stdin();
             if stdin.is_terminal() {
                 println!("## Type some text and it will be output as morse code.");
@@ -127,56 +562,136 @@ fn main() {
             for line in stdin.lock().lines() {
                 match line {
                     Ok(line) => {
+                        if midi_file.is_some() {
+                            midi_tones.extend(match *morse {
+                                true => morse::morse_to_tones(&line, dot_duration, tone_freq),
+                                false => morse::encode_morse(&line, dot_duration, tone_freq),
+                            });
+                            midi_tones.push((0., dot_duration * 14));
+                        }
                         if text {
-                            // Output text instead of sound
+                            // Output text
                             if *morse {
                                 // stdin is already morse encoded, convert it to text:
                                 println!("{}", morse::code_to_text(&line));
-                                if sound {
-                                    player.play_morse(&line, dot_duration, tone_freq, rts_port);
-                                    player.play_gap(dot_duration * 14, rts_port);
-                                } else if gpio {
-                                    player.gpio_morse(&line, dot_duration, gpio_pin);
-                                    player.gpio_gap(dot_duration * 14, gpio_pin);
-                                }
                             } else {
                                 // Encode stdin as morse code:
                                 println!("{}", morse::text_to_morse(&line));
-                                if sound {
-                                    player.play(&line, dot_duration, tone_freq, rts_port);
-                                    player.play_gap(dot_duration * 14, rts_port);
-                                } else if gpio {
-                                    player.gpio(&line, dot_duration, gpio_pin);
-                                    player.gpio_gap(dot_duration * 14, gpio_pin);
-                                }
                             }
-                        } else if *morse {
-                            // stdin is already morse encoded:
-                            if gpio {
-                                player.gpio_morse(&line, dot_duration, gpio_pin);
-                                player.gpio_gap(dot_duration * 14, gpio_pin);
+                        }
+                        if let Some((_, keyer)) = winkeyer.as_mut() {
+                            let line = match *morse {
+                                true => morse::code_to_text(&line),
+                                false => line,
+                            };
+                            if let Err(e) = keyer.send(&line) {
+                                error!("{e:#}");
+                            }
+                        } else if let Some(rig) = rig_keyer.as_mut() {
+                            let line = match *morse {
+                                true => morse::code_to_text(&line),
+                                false => line,
+                            };
+                            if let Err(e) = rig.send_morse(&line) {
+                                error!("{e:#}");
+                            }
+                        } else if let Some(key) = key_line.as_mut() {
+                            if *morse {
+                                player.key_morse(&line, dot_duration, key.as_mut());
                             } else {
-                                // Sound is the default:
-                                player.play_morse(&line, dot_duration, tone_freq, rts_port);
-                                player.play_gap(dot_duration * 14, rts_port);
+                                player.key(&line, dot_duration, key.as_mut());
                             }
-                        } else {
-                            // Convert stdin into morse and play it:
-                            if gpio {
-                                player.gpio(&line, dot_duration, gpio_pin);
-                                player.gpio_gap(dot_duration * 14, gpio_pin);
+                            player.key_gap(dot_duration * 14, key.as_mut());
+                        } else if !text || sound {
+                            // Sound is the default:
+                            if *morse {
+                                player.play_morse(&line, dot_duration, tone_freq);
                             } else {
-                                // Sound is the default:
-                                player.play(&line, dot_duration, tone_freq, rts_port);
-                                player.play_gap(dot_duration * 14, rts_port);
+                                player.play(&line, dot_duration, tone_freq);
                             }
+                            player.play_gap(dot_duration * 14);
                         }
                     }
                     Err(e) => eprintln!("Error reading line: {}", e),
                 }
             }
+            let written = midi_file.map(|path| midi::write_smf(path, &midi_tones, volume));
+            if let Some(Err(e)) = written {
+                error!("{e:#}");
+                shutdown::exit(1);
+            }
             0
         }
+        Some(("mix", sub_matches)) => {
+            let mut mixer = mixer::Mixer::new();
+            for spec in sub_matches
+                .get_many::<String>("station")
+                .expect("Missing --station arg")
+            {
+                match mixer::Station::parse(spec, tone_freq, dot_duration) {
+                    Ok(station) => {
+                        debug!("{:?}", station);
+                        mixer.add(station);
+                    }
+                    Err(e) => {
+                        error!("Invalid --station `{spec}`: {e:#}");
+                        shutdown::exit(1);
+                    }
+                }
+            }
+            match sub_matches.get_one::<String>("output") {
+                Some(path) => match mixer.write_wav(path) {
+                    Ok(()) => {
+                        info!("Wrote mix to {path}");
+                        0
+                    }
+                    Err(e) => {
+                        error!("{e:#}");
+                        1
+                    }
+                },
+                None => {
+                    let player = open_player();
+                    player.play_samples(
+                        mixer::Mixer::CHANNELS,
+                        mixer.sample_rate(),
+                        mixer.render(),
+                    );
+                    0
+                }
+            }
+        }
+        Some(("cwdaemon", sub_matches)) => {
+            let port = *sub_matches
+                .get_one::<u16>("port")
+                .expect("Missing --port arg default");
+            let address = sub_matches
+                .get_one::<String>("address")
+                .expect("Missing --address arg default");
+            let player = open_player();
+            match open_cw_output().and_then(|output| {
+                cwdaemon::run(address, port, &player, output, dot_duration, tone_freq)
+            }) {
+                Ok(()) => 0,
+                Err(e) => {
+                    error!("{e:#}");
+                    1
+                }
+            }
+        }
+        Some(("winkeyer-emulator", sub_matches)) => {
+            let link = sub_matches.get_one::<String>("link").map(|s| s.as_str());
+            let player = open_player();
+            match open_cw_output().and_then(|output| {
+                winkeyer_emulator::run(link, &player, output, dot_duration, tone_freq)
+            }) {
+                Ok(()) => 0,
+                Err(e) => {
+                    error!("{e:#}");
+                    1
+                }
+            }
+        }
         Some(("receive", sub_matches)) => {
             //
             let morse = sub_matches
@@ -186,10 +701,14 @@ fn main() {
                 .get_one::<bool>("listen")
                 .copied()
                 .unwrap_or(false);
-            if gpio {
-                // Receive from GPIO
-                gpio::gpio_receive(dot_duration, gpio_pin, *morse)
-                    .expect("Unhandled SIGINT or other fault");
+            let keyed = receive_keyed(sub_matches, &mut |source| {
+                open_decoder(sub_matches, source, open_rig())
+            });
+            if let Some(result) = keyed {
+                if let Err(e) = result {
+                    error!("{e:#}");
+                    shutdown::exit(1);
+                }
             } else if listen {
                 // Receive from audio device
                 let device = sub_matches
@@ -207,30 +726,50 @@ fn main() {
                 match (&device, &file) {
                     (None, Some(_file)) => {
                         error!("TODO. Audio file input is not supported yet.");
-                        std::process::exit(1);
+                        shutdown::exit(1);
                     }
                     (Some(_device), None) => {
-                        error!("TODO. Setting the input device name is not supported yet. Leave this setting unset to use the default device.");
-                        std::process::exit(1);
+                        error!(
+                            "TODO. Setting the input device name is not supported yet. Leave this setting unset to use the default device."
+                        );
+                        shutdown::exit(1);
                     }
                     (Some(_device), Some(_file)) => {
                         error!("Cannot specify --device and --file simultaneousy.");
-                        std::process::exit(1);
+                        shutdown::exit(1);
                     }
                     _ => {}
                 }
                 if cfg!(target_os = "linux") {
                     ensure_pipewire();
-                    pipewire::listen(tone_freq, bandwidth, threshold, dot_duration, *morse)
-                        .expect("pipewire::listen() failed");
+                    let recorder = match open_recorder(sub_matches, "audio") {
+                        Ok(recorder) => recorder,
+                        Err(e) => {
+                            error!("{e:#}");
+                            shutdown::exit(1);
+                        }
+                    };
+                    pipewire::listen(
+                        tone_freq,
+                        bandwidth,
+                        threshold,
+                        dot_duration,
+                        *morse,
+                        open_rig(),
+                        recorder,
+                    )
+                    .expect("pipewire::listen() failed");
                 } else {
                     error!("Sorry, the listen feature is only supported on Linux right now.");
-                    std::process::exit(1);
+                    shutdown::exit(1);
                 }
             } else {
                 // No valid input source specified
                 eprintln!("Error: You must specify an input method. Try one of:");
                 eprintln!("  --gpio <PIN>");
+                eprintln!("  --gpio-dit <PIN> --gpio-dah <PIN>");
+                eprintln!("  --input-device <PATH>");
+                eprintln!("  --midi-in <PORT>");
                 eprintln!("  --listen");
                 eprintln!("  --device <name> (not implemented yet)");
                 eprintln!("  --file <path>   (not implemented yet)");
@@ -240,10 +779,116 @@ fn main() {
                     .print_help()
                     .unwrap();
                 println!();
-                std::process::exit(1);
+                shutdown::exit(1);
             }
             0
         }
+        Some(("practice-key", sub_matches)) => {
+            let mut player = open_live_player();
+            match open_live_keys(&mut player, Vec::new()).and_then(|keys| {
+                let decoder = open_decoder(sub_matches, "keyboard", None)?;
+                practice_key::run(keyer_settings, Box::new(keys), decoder)
+            }) {
+                Ok(()) => 0,
+                Err(e) => {
+                    error!("{e:#}");
+                    1
+                }
+            }
+        }
+        Some(("send-practice", sub_matches)) => {
+            let targets = match sub_matches.get_many::<String>("target") {
+                Some(targets) => targets.cloned().collect(),
+                None => send_practice::targets(
+                    sub_matches
+                        .get_one::<String>("targets")
+                        .expect("Missing --targets arg default")
+                        .parse()
+                        .expect("Invalid --targets value"),
+                    *sub_matches
+                        .get_one::<u32>("trials")
+                        .expect("Missing --trials arg default"),
+                ),
+            };
+            let mut trainer = Some(send_practice::SendTrainer::new(targets, dot_duration));
+            let mut open_trainer = |_source: &str| {
+                Ok(decode::LiveDecoder::new(dot_duration, false, None).practice(trainer.take()))
+            };
+            let result = match receive_keyed(sub_matches, &mut open_trainer) {
+                Some(result) => result,
+                None => {
+                    // Key from the computer keyboard
+                    let mut player = open_live_player();
+                    open_live_keys(&mut player, Vec::new()).and_then(|keys| {
+                        let decoder = open_trainer("keyboard")?;
+                        practice_key::run(keyer_settings, Box::new(keys), decoder)
+                    })
+                }
+            };
+            match result {
+                Ok(()) => 0,
+                Err(e) if send_practice::is_finished(&e) => 0,
+                Err(e) => {
+                    error!("{e:#}");
+                    1
+                }
+            }
+        }
+        Some(("replay", sub_matches)) => {
+            let path = sub_matches
+                .get_one::<String>("file")
+                .expect("Missing FILE arg");
+            let speed = *sub_matches
+                .get_one::<f32>("speed")
+                .expect("Missing --speed arg default");
+            let instant = sub_matches.get_flag("instant");
+            // Nothing is keyed when decoding at once:
+            let mut player = (!instant).then(open_live_player);
+            let result = cwlog::read(path).and_then(|(header, edges)| {
+                info!(
+                    "Replaying {path}: {}, {} WPM, recorded {}",
+                    header.source, header.wpm, header.started
+                );
+                let decoder =
+                    decode::LiveDecoder::new(header.dot_ms, sub_matches.get_flag("morse"), None)
+                        .analyze(sub_matches.get_flag("analyze"));
+                let keys = match player.as_mut() {
+                    Some(player) => open_live_keys(player, Vec::new())?,
+                    None => Vec::new(),
+                };
+                let keying = decode::DecodedKey::new(Box::new(keys), decoder);
+                cwlog::replay(&edges, speed, keying, header.dot_ms, instant)
+            });
+            match result {
+                Ok(_) => 0,
+                Err(e) => {
+                    error!("{e:#}");
+                    1
+                }
+            }
+        }
+        Some(("decode", sub_matches)) => {
+            let path = sub_matches
+                .get_one::<String>("timings")
+                .expect("Missing --timings arg");
+            match timings::read(path) {
+                Ok(timings) => {
+                    // The speed of the file, unless --dot or --wpm is given:
+                    let speed_given = matches.contains_id("dot") || matches.contains_id("wpm");
+                    let dot_duration = match timings.dot_ms {
+                        Some(dot_ms) if !speed_given => dot_ms,
+                        _ => dot_duration,
+                    };
+                    let decoded = timings::decode(&timings.runs, dot_duration);
+                    timings::print_decoded(&decoded, sub_matches.get_flag("morse"));
+                    0
+                }
+                Err(e) => {
+                    error!("{e:#}");
+                    1
+                }
+            }
+        }
         Some(("completions", sub_matches)) => {
             if let Some(shell) = sub_matches.get_one::<String>("shell") {
                 match shell.as_str() {
@@ -288,7 +933,7 @@ fn main() {
     };
 
     eprintln!();
-    std::process::exit(exit_code);
+    shutdown::exit(exit_code);
 }
 
 fn generate_completion_script(shell: clap_complete::shells::Shell) {
@@ -1,5 +1,6 @@
 #[allow(dead_code)]
 pub struct Message {
-    pub timestamp: String, // Timestamp in the format `YY-MM-DD HH:MM:SS`
-    pub content: String,   // The actual message content
+    pub timestamp: String,   // Timestamp in the format `YY-MM-DD HH:MM:SS`
+    pub content: String,     // The actual message content
+    pub rig: Option<String>, // Frequency and mode of the rig (from rigctld)
 }
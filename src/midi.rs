@@ -0,0 +1,286 @@
+//! MIDI keyer adapters and outputs: note on/off from an adapter keys
+//! the straight key or the paddles, and the element timeline can be
+//! played as notes on a MIDI port (synths, lighting) or written to a
+//! Standard MIDI File.
+use crate::decode::LiveDecoder;
+#[cfg(feature = "midi")]
+use crate::input::{receive_contacts, Contact, ContactEvent};
+use crate::key::Key;
+use crate::keyer::KeyerSettings;
+#[cfg(feature = "midi")]
+use crate::prelude::*;
+#[cfg(feature = "midi")]
+use anyhow::anyhow;
+use anyhow::Context;
+use midly::num::{u15, u24, u28, u4, u7};
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+#[cfg(feature = "midi")]
+use std::sync::mpsc;
+#[cfg(feature = "midi")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "midi")]
+const NOTE_ON: u8 = 0x90;
+#[cfg(feature = "midi")]
+const NOTE_OFF: u8 = 0x80;
+
+/// The notes of the contacts of a MIDI adapter (0, 1 and 2 for the
+/// Vail adapter in MIDI mode)
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(not(feature = "midi"), allow(dead_code))]
+pub struct MidiNotes {
+    pub straight: u8,
+    pub dit: u8,
+    pub dah: u8,
+}
+
+/// The MIDI note closest to a tone frequency (A4 = 440 Hz is note 69).
+pub fn note_number(tone_freq: f32) -> u8 {
+    (69. + 12. * (tone_freq / 440.).log2())
+        .round()
+        .clamp(0., 127.) as u8
+}
+
+/// Finds a port by (part of) its name, or by its number.
+#[cfg(feature = "midi")]
+fn find_port<T: midir::MidiIO>(io: &T, name: &str, direction: &str) -> anyhow::Result<T::Port> {
+    let ports = io.ports();
+    let names: Vec<String> = ports
+        .iter()
+        .map(|port| io.port_name(port).unwrap_or_default())
+        .collect();
+    let found = match name.parse::<usize>() {
+        Ok(number) => ports.get(number),
+        Err(_) => names
+            .iter()
+            .position(|port_name| port_name.to_lowercase().contains(&name.to_lowercase()))
+            .map(|i| &ports[i]),
+    };
+    found.cloned().ok_or_else(|| match names.is_empty() {
+        true => anyhow!(
+            "MIDI {direction} port `{name}` not found (there are no MIDI {direction} ports)"
+        ),
+        false => anyhow!(
+            "MIDI {direction} port `{name}` not found (available: {})",
+            names.join(", ")
+        ),
+    })
+}
+
+/// Keys `key` (sidetone, output lines, PTT, ...) from the note on/off
+/// messages of a MIDI adapter, the paddles through the keyer, and
+/// decodes what is sent.
+#[cfg(feature = "midi")]
+pub fn midi_receive(
+    port_name: &str,
+    notes: MidiNotes,
+    settings: KeyerSettings,
+    key: Box<dyn Key>,
+    decoder: LiveDecoder,
+) -> anyhow::Result<()> {
+    let input = midir::MidiInput::new(env!("CARGO_PKG_NAME")).context("opening MIDI input")?;
+    let port = find_port(&input, port_name, "input")?;
+    let full_name = input.port_name(&port).unwrap_or_default();
+
+    let start = Instant::now();
+    let (sender, receiver) = mpsc::channel();
+    // The message timestamps (in µs) count from an unspecified start,
+    // line them up with ours on the first message:
+    let mut offset: Option<i128> = None;
+    let _connection = input
+        .connect(
+            &port,
+            "code-smore-in",
+            move |stamp, message, _| {
+                let (status, note, velocity) = match message {
+                    [status, note, velocity] => (status & 0xf0, *note, *velocity),
+                    _ => return,
+                };
+                let closed = match status {
+                    NOTE_ON => velocity > 0,
+                    NOTE_OFF => false,
+                    _ => return,
+                };
+                let contact = match note {
+                    note if note == notes.straight => Contact::Straight,
+                    note if note == notes.dit => Contact::Dit,
+                    note if note == notes.dah => Contact::Dah,
+                    _ => return,
+                };
+                let offset =
+                    *offset.get_or_insert(start.elapsed().as_micros() as i128 - stamp as i128);
+                let at = Duration::from_micros((stamp as i128 + offset).max(0) as u64);
+                let _ = sender.send(Ok(ContactEvent {
+                    contact,
+                    closed,
+                    at,
+                }));
+            },
+            (),
+        )
+        .map_err(|e| anyhow!("connecting to MIDI input port {full_name}: {e}"))?;
+
+    clear_screen();
+    info!("Keying from MIDI input port {full_name} - Press Ctrl-C to stop.");
+    receive_contacts(receiver, move || start.elapsed(), settings, key, decoder)
+}
+
+#[cfg(not(feature = "midi"))]
+pub fn midi_receive(
+    _port_name: &str,
+    _notes: MidiNotes,
+    _settings: KeyerSettings,
+    _key: Box<dyn Key>,
+    _decoder: LiveDecoder,
+) -> anyhow::Result<()> {
+    anyhow::bail!("The 'midi' feature is not enabled in this crate build")
+}
+
+/// Plays a note on a MIDI output port while the key is down
+#[cfg(feature = "midi")]
+pub struct MidiKey {
+    connection: midir::MidiOutputConnection,
+    note: u8,
+    velocity: u8,
+}
+
+#[cfg(feature = "midi")]
+impl MidiKey {
+    pub fn open(port_name: &str, tone_freq: f32, volume: f32) -> anyhow::Result<Self> {
+        let output =
+            midir::MidiOutput::new(env!("CARGO_PKG_NAME")).context("opening MIDI output")?;
+        let port = find_port(&output, port_name, "output")?;
+        let full_name = output.port_name(&port).unwrap_or_default();
+        let connection = output
+            .connect(&port, "code-smore-out")
+            .map_err(|e| anyhow!("connecting to MIDI output port {full_name}: {e}"))?;
+        Ok(Self {
+            connection,
+            note: note_number(tone_freq),
+            velocity: (volume * 127.).round().clamp(1., 127.) as u8,
+        })
+    }
+}
+
+#[cfg(feature = "midi")]
+impl Key for MidiKey {
+    fn set(&mut self, down: bool) -> anyhow::Result<()> {
+        let message = match down {
+            true => [NOTE_ON, self.note, self.velocity],
+            false => [NOTE_OFF, self.note, 0],
+        };
+        self.connection
+            .send(&message)
+            .context("sending a MIDI message")
+    }
+}
+
+#[cfg(not(feature = "midi"))]
+pub struct MidiKey;
+
+#[cfg(not(feature = "midi"))]
+impl MidiKey {
+    pub fn open(_port_name: &str, _tone_freq: f32, _volume: f32) -> anyhow::Result<Self> {
+        anyhow::bail!("The 'midi' feature is not enabled in this crate build")
+    }
+}
+
+#[cfg(not(feature = "midi"))]
+impl Key for MidiKey {
+    fn set(&mut self, _down: bool) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Renders tones (frequency, duration in ms) as a Standard MIDI File
+/// with a single track, one note per tone, at one tick per millisecond.
+pub fn render_smf(tones: &[(f32, u32)], volume: f32) -> Vec<u8> {
+    let velocity = u7::new((volume * 127.).round().clamp(1., 127.) as u8);
+    let event = |delta: u32, kind| TrackEvent {
+        delta: u28::new(delta),
+        kind,
+    };
+    let midi = |message| TrackEventKind::Midi {
+        channel: u4::new(0),
+        message,
+    };
+
+    // 500 ticks per beat at 120 BPM (500000 µs per beat):
+    let tempo = MetaMessage::Tempo(u24::new(500_000));
+    let mut track = vec![event(0, TrackEventKind::Meta(tempo))];
+    let mut delta = 0; // Time since the last event
+    for &(freq, duration) in tones {
+        if freq > 0. && duration > 0 {
+            let key = u7::new(note_number(freq));
+            let on = MidiMessage::NoteOn { key, vel: velocity };
+            let off = MidiMessage::NoteOff {
+                key,
+                vel: u7::new(0),
+            };
+            track.push(event(delta, midi(on)));
+            track.push(event(duration, midi(off)));
+            delta = 0;
+        } else {
+            delta += duration;
+        }
+    }
+    track.push(event(delta, TrackEventKind::Meta(MetaMessage::EndOfTrack)));
+
+    let timing = Timing::Metrical(u15::new(500));
+    let mut smf = Smf::new(Header::new(Format::SingleTrack, timing));
+    smf.tracks.push(track);
+    let mut bytes = Vec::new();
+    smf.write_std(&mut bytes)
+        .expect("writing to memory cannot fail");
+    bytes
+}
+
+/// Writes tones (frequency, duration in ms) to a Standard MIDI File.
+pub fn write_smf(path: &str, tones: &[(f32, u32)], volume: f32) -> anyhow::Result<()> {
+    std::fs::write(path, render_smf(tones, volume))
+        .with_context(|| format!("writing MIDI file {path}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::morse::encode_morse;
+
+    #[test]
+    fn test_note_number() {
+        assert_eq!(note_number(440.), 69);
+        assert_eq!(note_number(880.), 81);
+        assert_eq!(note_number(700.), 77);
+    }
+
+    #[test]
+    fn test_render_smf() {
+        let bytes = render_smf(&encode_morse("ET", 60, 440.), 1.0);
+        let smf = Smf::parse(&bytes).unwrap();
+        assert_eq!(smf.header.timing, Timing::Metrical(u15::new(500)));
+
+        // Note on/off at their times in ticks (ms):
+        let mut time = 0;
+        let mut notes = Vec::new();
+        for event in &smf.tracks[0] {
+            time += event.delta.as_int();
+            if let TrackEventKind::Midi { message, .. } = event.kind {
+                match message {
+                    MidiMessage::NoteOn { key, .. } => notes.push((time, key.as_int(), true)),
+                    MidiMessage::NoteOff { key, .. } => notes.push((time, key.as_int(), false)),
+                    _ => {}
+                }
+            }
+        }
+        // E is a dot, and T (a dash) follows after the character gap:
+        assert_eq!(
+            notes,
+            [
+                (0, 69, true),
+                (60, 69, false),
+                (360, 69, true),
+                (540, 69, false)
+            ]
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use crate::morse::{encode_morse, wpm_to_dot_length, Tone, SAMPLE_RATE};
+use crate::prelude::*;
+use anyhow::{bail, Context};
+
+/// A single CW signal in the mix
+#[derive(Debug, Clone, PartialEq)]
+pub struct Station {
+    pub text: String,
+    pub tone_freq: f32,    // Pitch of this signal in Hz
+    pub dot_duration: u32, // Speed of this signal as a dot length in ms
+    pub amplitude: f32,    // Signal strength [0.0..1.0]
+    pub offset: u32,       // Start offset from the beginning of the mix in ms
+    pub pan: f32,          // Stereo position [-1.0 (left) .. 1.0 (right)]
+}
+
+impl Station {
+    /// Parses a station spec of the form `[key=value,...:]TEXT`.
+    ///
+    /// Recognized keys are `tone` (Hz), `wpm`, `dot` (ms), `amp`
+    /// [0.0..1.0], `offset` (ms) and `pan` [-1.0..1.0]. Any key not
+    /// given falls back to the provided defaults (or centered, full
+    /// amplitude, no offset).
+    ///
+    /// # Example
+    /// `tone=650,wpm=28,pan=-0.5,offset=1500:CQ TEST K1ABC`
+    pub fn parse(spec: &str, tone_freq: f32, dot_duration: u32) -> anyhow::Result<Self> {
+        let mut station = Station {
+            text: spec.to_string(),
+            tone_freq,
+            dot_duration,
+            amplitude: 1.0,
+            offset: 0,
+            pan: 0.0,
+        };
+        let Some((options, text)) = spec.split_once(':') else {
+            return Ok(station);
+        };
+        if !options.contains('=') {
+            // No options, the colon is part of the text.
+            return Ok(station);
+        }
+        station.text = text.to_string();
+        for option in options.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+            let (key, value) = option
+                .split_once('=')
+                .with_context(|| format!("invalid station option `{option}`"))?;
+            let number = value
+                .trim()
+                .parse::<f32>()
+                .with_context(|| format!("invalid value for station option `{key}`"))?;
+            match key.trim() {
+                "tone" if number > 0.0 => station.tone_freq = number,
+                "wpm" if number >= 1.0 => station.dot_duration = wpm_to_dot_length(number as u32),
+                "dot" if number >= 1.0 => station.dot_duration = number as u32,
+                "amp" if (0.0..=1.0).contains(&number) => station.amplitude = number,
+                "offset" if number >= 0.0 => station.offset = number as u32,
+                "pan" if (-1.0..=1.0).contains(&number) => station.pan = number,
+                "tone" | "wpm" | "dot" | "amp" | "offset" | "pan" => {
+                    bail!("station option `{key}` is out of range: {value}")
+                }
+                _ => bail!("unknown station option `{key}`"),
+            }
+        }
+        Ok(station)
+    }
+
+    /// Constant power pan law, returns the (left, right) channel gains.
+    fn gains(&self) -> (f32, f32) {
+        let angle = (self.pan + 1.0) * std::f32::consts::FRAC_PI_4;
+        (self.amplitude * angle.cos(), self.amplitude * angle.sin())
+    }
+}
+
+/// Renders several independent CW streams into one stereo signal
+pub struct Mixer {
+    sample_rate: u32,
+    stations: Vec<Station>,
+}
+
+impl Mixer {
+    pub const CHANNELS: u16 = 2;
+
+    pub fn new() -> Self {
+        Self {
+            sample_rate: SAMPLE_RATE,
+            stations: Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn add(&mut self, station: Station) {
+        self.stations.push(station);
+    }
+
+    /// Renders all stations into interleaved stereo samples.
+    ///
+    /// The mix is scaled down if the combined signals would clip.
+    pub fn render(&self) -> Vec<f32> {
+        let ms_to_samples = |ms: u32| (self.sample_rate as u64 * ms as u64 / 1000) as usize;
+        let mut samples: Vec<f32> = Vec::new();
+
+        for station in &self.stations {
+            let (left, right) = station.gains();
+            let mut cursor = ms_to_samples(station.offset);
+            for (freq, duration) in
+                encode_morse(&station.text, station.dot_duration, station.tone_freq)
+            {
+                let length = ms_to_samples(duration);
+                if samples.len() < (cursor + length) * 2 {
+                    samples.resize((cursor + length) * 2, 0.0);
+                }
+                if freq > 0.0 {
+                    for (i, sample) in Tone::new(freq, duration, self.sample_rate)
+                        .take(length)
+                        .enumerate()
+                    {
+                        samples[(cursor + i) * 2] += sample * left;
+                        samples[(cursor + i) * 2 + 1] += sample * right;
+                    }
+                }
+                cursor += length;
+            }
+        }
+
+        let peak = samples.iter().fold(0.0_f32, |peak, s| peak.max(s.abs()));
+        if peak > 1.0 {
+            debug!("Mix peak is {peak:.2}, normalizing.");
+            samples.iter_mut().for_each(|s| *s /= peak);
+        }
+        samples
+    }
+
+    /// Renders the mix and writes it to a 16-bit stereo WAV file.
+    pub fn write_wav(&self, path: &str) -> anyhow::Result<()> {
+        let spec = hound::WavSpec {
+            channels: Self::CHANNELS,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec)
+            .with_context(|| format!("creating WAV file `{path}`"))?;
+        for sample in self.render() {
+            writer.write_sample((sample * i16::MAX as f32) as i16)?;
+        }
+        writer.finalize().context("finalizing WAV file")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_station() {
+        let station = Station::parse(
+            "tone=650,wpm=30,pan=-1,amp=0.5,offset=250:CQ TEST",
+            440.0,
+            60,
+        )
+        .unwrap();
+        assert_eq!(station.text, "CQ TEST");
+        assert_eq!(station.tone_freq, 650.0);
+        assert_eq!(station.dot_duration, 40);
+        assert_eq!(station.amplitude, 0.5);
+        assert_eq!(station.offset, 250);
+        assert_eq!(station.pan, -1.0);
+
+        let station = Station::parse("QRL?", 440.0, 60).unwrap();
+        assert_eq!((station.text.as_str(), station.tone_freq), ("QRL?", 440.0));
+        assert!(Station::parse("pan=2:K", 440.0, 60).is_err());
+        assert!(Station::parse("pitch=600:K", 440.0, 60).is_err());
+    }
+
+    #[test]
+    fn test_render_pan_and_offset() {
+        let mut mixer = Mixer::new();
+        mixer.add(Station::parse("pan=-1:E", 600.0, 60).unwrap());
+        mixer.add(Station::parse("pan=1,offset=1000:E", 800.0, 60).unwrap());
+        let samples = mixer.render();
+        // Second station starts one second in and lasts one dot plus the trailing gap:
+        assert_eq!(samples.len(), (SAMPLE_RATE as usize + 2 * 2646) * 2);
+        let (left, right): (Vec<f32>, Vec<f32>) =
+            samples.chunks(2).map(|frame| (frame[0], frame[1])).unzip();
+        let half = SAMPLE_RATE as usize;
+        assert!(left[..half].iter().any(|s| s.abs() > 0.5));
+        assert!(right[..half].iter().all(|s| s.abs() < 1e-3));
+        assert!(left[half..].iter().all(|s| s.abs() < 1e-3));
+        assert!(right[half..].iter().any(|s| s.abs() > 0.5));
+    }
+}
@@ -1,28 +1,54 @@
 #![allow(unused_imports)]
+use crate::gpio::{GpioBuzzer, GpioOutput};
+use crate::key::{key_morse_code, Key};
+#[cfg(target_os = "linux")]
+#[cfg(feature = "pipewire")]
+use crate::pipewire::PipewireSource;
 use crate::prelude::*;
-use anyhow;
+use crate::ptt::{Ptt, PttHold};
+use crate::shutdown;
 use anyhow::Context;
+use morse_codec::decoder::{Decoder, MorseDecoder};
 #[cfg(feature = "audio")]
 use rodio::{OutputStream, Sink, Source};
-#[cfg(feature = "gpio")]
-use rppal;
-#[cfg(feature = "audio")]
-use serialport::SerialPort;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 #[allow(unused_imports)]
 use std::thread::{self, sleep};
 use std::time::Duration;
 
+/// Sample rate used for all generated audio
+pub const SAMPLE_RATE: u32 = 44_100;
+
 /// Custom audio source for generating tones
 #[allow(dead_code)]
-struct Tone {
+pub(crate) struct Tone {
     freq: f32,        // Frequency of the tone in Hz
     duration: u32,    // Duration of the tone in milliseconds
     sample_rate: u32, // Sample rate in Hz
+    gain: f32,        // Output amplitude [0.0..1.0]
     current_sample: u32,
 }
 
+impl Tone {
+    pub fn new(freq: f32, duration: u32, sample_rate: u32) -> Self {
+        Self {
+            freq,
+            duration,
+            sample_rate,
+            gain: 1.0,
+            current_sample: 0,
+        }
+    }
+
+    /// Scales the amplitude of the tone (e.g. from the `--volume` setting)
+    pub fn with_gain(mut self, gain: f32) -> Self {
+        self.gain = gain;
+        self
+    }
+}
+
 impl Iterator for Tone {
     type Item = f32;
 
@@ -49,7 +75,7 @@ impl Iterator for Tone {
         };
 
         self.current_sample += 1;
-        Some(sample * amplitude)
+        Some(sample * amplitude * self.gain)
     }
 }
 
@@ -72,12 +98,142 @@ impl Source for Tone {
     }
 }
 
+/// A continuous tone gated on and off by a key, for sidetone. The
+/// amplitude ramps over 5ms, so keying doesn't click.
+#[cfg(feature = "audio")]
+struct GatedTone {
+    freq: f32,
+    sample_rate: u32,
+    gain: f32,
+    gate: Arc<AtomicBool>,
+    amplitude: f32,
+    phase: f32, // Position in the current cycle [0.0..1.0)
+}
+
+#[cfg(feature = "audio")]
+impl Iterator for GatedTone {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ramp = 1.0 / (0.005 * self.sample_rate as f32);
+        self.amplitude = if self.gate.load(Ordering::Relaxed) {
+            (self.amplitude + ramp).min(1.0)
+        } else {
+            (self.amplitude - ramp).max(0.0)
+        };
+        let sample = (2.0 * std::f32::consts::PI * self.phase).sin();
+        self.phase = (self.phase + self.freq / self.sample_rate as f32).fract();
+        Some(sample * self.amplitude * self.gain)
+    }
+}
+
+#[cfg(feature = "audio")]
+impl Source for GatedTone {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Sidetone that sounds while the key is down (e.g. for a keyer)
+pub enum Sidetone {
+    #[cfg(feature = "audio")]
+    Sound {
+        gate: Arc<AtomicBool>,
+        _sink: Sink, // Keeps the tone playing
+    },
+    Buzzer(GpioBuzzer),
+}
+
+impl Key for Sidetone {
+    fn set(&mut self, down: bool) -> anyhow::Result<()> {
+        match self {
+            #[cfg(feature = "audio")]
+            Sidetone::Sound { gate, .. } => {
+                gate.store(down, Ordering::Relaxed);
+                Ok(())
+            }
+            Sidetone::Buzzer(buzzer) => buzzer.set(down),
+        }
+    }
+}
+
 /// Converts words per minute (WPM) into a dot length in milliseconds
 /// Based on standard Morse code timing where "PARIS" defines one word.
 pub fn wpm_to_dot_length(wpm: u32) -> u32 {
     1200 / wpm
 }
 
+/// Converts a dot length in milliseconds into words per minute (WPM)
+pub fn dot_length_to_wpm(dot_duration: u32) -> u32 {
+    1200 / dot_duration
+}
+
+/// The dot length the decoder works with: durations are scaled to it,
+/// so that its thresholds (a dot is anything up to 1.5 dots + 50ms)
+/// come out at 2 dots whatever the speed.
+const DECODER_DOT_MS: f32 = 100.;
+/// The space (in dots) after which the decoder ends a word
+const DECODER_WORD_GAP_DOTS: f32 = 8.;
+/// The space (in dots) that ends a word: a standard word gap
+const WORD_GAP_DOTS: f32 = 7.;
+
+/// Decodes key down/up durations into text
+pub struct CwDecoder {
+    decoder: MorseDecoder<9999>,
+    dot_duration: u32,
+}
+
+impl CwDecoder {
+    /// The key was down (`is_high`) or up for `duration_ms`.
+    pub fn signal_event(&mut self, duration_ms: u16, is_high: bool) {
+        let dots = f32::from(duration_ms) / self.dot_duration.max(1) as f32;
+        // A standard word gap is shorter than the decoder waits for:
+        let dots = match is_high {
+            false if dots >= WORD_GAP_DOTS => dots.max(DECODER_WORD_GAP_DOTS),
+            _ => dots,
+        };
+        let duration = (dots * DECODER_DOT_MS).min(f32::from(u16::MAX)) as u16;
+        self.decoder.signal_event(duration, is_high);
+    }
+
+    /// Ends the current character (and the word, with `end_word`).
+    pub fn signal_event_end(&mut self, end_word: bool) {
+        self.decoder.signal_event_end(end_word);
+    }
+
+    /// The text decoded so far
+    pub fn message(&self) -> &str {
+        self.decoder.message.as_str()
+    }
+
+    #[allow(dead_code)] // Only used when receiving with PipeWire
+    pub fn clear_message(&mut self) {
+        self.decoder.message.clear();
+    }
+}
+
+/// Creates a morse code decoder expecting the given dot duration
+pub fn get_decoder(dot_duration: u32) -> CwDecoder {
+    CwDecoder {
+        decoder: Decoder::<9999>::new()
+            .with_reference_short_ms(DECODER_DOT_MS as u16)
+            .build(),
+        dot_duration,
+    }
+}
+
 fn get_morse_maps() -> (HashMap<char, String>, HashMap<String, char>) {
     let forward_map = vec![
         ('A', ".-".to_string()),
@@ -173,7 +329,7 @@ pub fn text_to_morse(text: &str) -> String {
 }
 
 #[allow(dead_code)]
-fn encode_morse(text: &str, dot_duration: u32, tone_freq: f32) -> Vec<(f32, u32)> {
+pub(crate) fn encode_morse(text: &str, dot_duration: u32, tone_freq: f32) -> Vec<(f32, u32)> {
     let morse_code = text_to_morse(text);
     let morse_code = regex::Regex::new(r"\s{3,}") // Match three or more spaces
         .unwrap()
@@ -188,7 +344,11 @@ fn encode_morse(text: &str, dot_duration: u32, tone_freq: f32) -> Vec<(f32, u32)
 }
 
 #[allow(dead_code)]
-fn morse_to_tones(morse_code: &str, dot_duration: u32, tone_freq: f32) -> Vec<(f32, u32)> {
+pub(crate) fn morse_to_tones(
+    morse_code: &str,
+    dot_duration: u32,
+    tone_freq: f32,
+) -> Vec<(f32, u32)> {
     let dash_duration = dot_duration * 3; // Duration of a dash
     let char_gap_duration = dot_duration * 3; // Gap between characters
     let word_gap_duration = dot_duration * 7; // Gap between words
@@ -209,236 +369,484 @@ fn morse_to_tones(morse_code: &str, dot_duration: u32, tone_freq: f32) -> Vec<(f
     tones
 }
 
-/// RAII guard that asserts RTS on construction and de-asserts on drop.
+/// Play a sequence of (frequency, duration_ms) via the given Sink at
+/// the given volume, blocking until playback finishes.
 #[cfg(feature = "audio")]
-struct RtsGuard {
-    port: Box<dyn SerialPort>,
-}
-
-#[cfg(feature = "audio")]
-impl RtsGuard {
-    pub fn new(port_name: &str) -> anyhow::Result<Self> {
-        let mut port = serialport::new(port_name, 9_600)
-            .timeout(Duration::from_millis(100))
-            .open()
-            .with_context(|| format!("opening serial port `{}`", port_name))?;
-        port.write_request_to_send(true).context("asserting RTS")?;
-        debug!("RTS ON");
-        Ok(RtsGuard { port })
+pub fn play_morse_code(tones: Vec<(f32, u32)>, sink: &Sink, volume: f32) {
+    for (freq, duration) in tones {
+        sink.append(Tone::new(freq, duration, SAMPLE_RATE).with_gain(volume));
     }
-}
 
-#[cfg(feature = "audio")]
-impl Drop for RtsGuard {
-    fn drop(&mut self) {
-        // best-effort deassert
-        let _ = self.port.write_request_to_send(false);
-        debug!("RTS OFF");
-    }
+    // block current thread until playback finishes
+    sink.sleep_until_end();
 }
 
-/// Play a sequence of (frequency, duration_ms) via the given Sink.
-/// If `rts_port` is `Some("/dev/ttyUSB0")` it will raise RTS for the
-/// entire duration of the playback, then lower it at the end.
-#[cfg(feature = "audio")]
-pub fn play_morse_code(
-    tones: Vec<(f32, u32)>,
-    sink: &Sink,
-    rts_port: Option<&str>,
-) -> anyhow::Result<()> {
-    // If requested, open the port and assert RTS.
-    // The guard lives until end of this function (i.e. until after playback).
-    let _rts = match rts_port {
-        Some(port_name) => Some(RtsGuard::new(port_name)?),
-        None => None,
-    };
-
-    let sample_rate = 44_100;
-    for (freq, duration) in tones {
-        sink.append(Tone {
-            freq,
-            duration,
-            sample_rate,
-            current_sample: 0,
-        });
+/// Splits a sequence of (frequency, duration_ms) into characters, each
+/// followed by the gap after it. Characters are separated by two or
+/// more gaps in a row (the element gap plus a character or word gap).
+pub(crate) fn split_characters(tones: Vec<(f32, u32)>) -> Vec<Vec<(f32, u32)>> {
+    let mut characters = Vec::new();
+    let mut current = Vec::new();
+    let mut gaps = 0;
+    for tone in tones {
+        if tone.0 > 0.0 {
+            if gaps >= 2 {
+                characters.push(std::mem::take(&mut current));
+            }
+            gaps = 0;
+        } else {
+            gaps += 1;
+        }
+        current.push(tone);
+    }
+    if !current.is_empty() {
+        characters.push(current);
     }
+    characters
+}
 
-    // block current thread until playback finishes
-    sink.sleep_until_end();
+/// Renders a sequence of (frequency, duration_ms) into mono samples.
+#[allow(dead_code)]
+pub(crate) fn render_tones(tones: Vec<(f32, u32)>, volume: f32) -> Vec<f32> {
+    tones
+        .into_iter()
+        .flat_map(|(freq, duration)| Tone::new(freq, duration, SAMPLE_RATE).with_gain(volume))
+        .collect()
+}
 
-    // _rts goes out of scope here, dropping RtsGuard and de-asserting RTS
-    Ok(())
+/// Where the player sends its sound
+#[derive(Clone)]
+enum AudioOutput {
+    #[cfg(feature = "audio")]
+    Device(Arc<rodio::OutputStreamHandle>), // Shareable stream handle
+    #[cfg(target_os = "linux")]
+    #[cfg(feature = "pipewire")]
+    PipeWire(Arc<PipewireSource>),
+    Buzzer(GpioBuzzer), // PWM tone on a GPIO pin
+    #[allow(dead_code)]
+    Disabled,
 }
 
-#[cfg(feature = "gpio")]
-fn gpio_morse_code(tones: Vec<(f32, u32)>, pin_number: u8) {
-    let mut pin = rppal::gpio::Gpio::new()
-        .expect("Failed to access GPIO")
-        .get(pin_number)
-        .expect("Failed to get GPIO pin")
-        .into_output();
-    for (frequency, duration) in tones {
-        if frequency == 0. || duration == 0 {
-            //info!("gap: d: {duration}");
-            pin.set_low();
-        } else {
-            //info!("f: {frequency} d: {duration}");
-            pin.set_high();
+impl AudioOutput {
+    /// Plays the tones, blocking until they are finished.
+    #[allow(unused_variables)]
+    fn play(&self, tones: Vec<(f32, u32)>, volume: f32) -> anyhow::Result<()> {
+        match self {
+            #[cfg(feature = "audio")]
+            AudioOutput::Device(stream_handle) => {
+                let sink = Sink::try_new(stream_handle)?;
+                play_morse_code(tones, &sink, volume);
+                Ok(())
+            }
+            #[cfg(target_os = "linux")]
+            #[cfg(feature = "pipewire")]
+            AudioOutput::PipeWire(source) => {
+                source.play(render_tones(tones, volume));
+                Ok(())
+            }
+            AudioOutput::Buzzer(buzzer) => {
+                if let Some(&(freq, _)) = tones.iter().find(|(freq, _)| *freq > 0.) {
+                    buzzer.set_frequency(freq)?;
+                }
+                key_morse_code(tones, &mut buzzer.clone())
+            }
+            AudioOutput::Disabled => anyhow::bail!(
+                "'audio' feature is disabled in this Cargo build. Program cannot play audio."
+            ),
         }
-        sleep(Duration::from_millis(duration.into()));
     }
-    pin.set_low();
+}
+
+/// Where the CW goes: played as sound, or keyed on a GPIO pin or
+/// serial line
+pub enum CwOutput {
+    Sound,
+    Key(Box<dyn Key>),
 }
 
 pub struct MorsePlayer {
     #[cfg(feature = "audio")]
     #[allow(dead_code)]
-    stream: Arc<OutputStream>, // Keep the stream alive
+    stream: Option<Arc<OutputStream>>, // Keep the stream alive
 
-    #[cfg(feature = "audio")]
-    stream_handle: Arc<rodio::OutputStreamHandle>, // Shareable stream handle
+    output: AudioOutput,
+
+    #[allow(dead_code)]
+    volume: f32, // Gain applied to every tone [0.0..1.0]
+
+    ptt: Option<Ptt>, // Keyed while transmitting
+}
+
+/// Lists the names of the available audio output devices.
+#[cfg(feature = "audio")]
+pub fn list_output_devices() -> anyhow::Result<Vec<String>> {
+    use rodio::cpal::traits::HostTrait;
+    use rodio::DeviceTrait;
+    let devices = rodio::cpal::default_host()
+        .output_devices()
+        .context("enumerating audio output devices")?;
+    Ok(devices.filter_map(|device| device.name().ok()).collect())
+}
+
+#[cfg(not(feature = "audio"))]
+pub fn list_output_devices() -> anyhow::Result<Vec<String>> {
+    anyhow::bail!(
+        "'audio' feature is disabled in this Cargo build. Program cannot list audio devices."
+    )
 }
 
 impl MorsePlayer {
-    pub fn new() -> Self {
+    /// Opens the named audio output device (or the default device if
+    /// `output_device` is `None`) and plays every tone at `volume`.
+    pub fn open(output_device: Option<&str>, volume: f32) -> anyhow::Result<Self> {
         #[cfg(feature = "audio")]
         {
+            use rodio::cpal::traits::HostTrait;
+            use rodio::DeviceTrait;
             // Set up the audio output once
-            let stream = OutputStream::try_default().unwrap();
-            let stream_handle = Arc::new(stream.1);
+            let stream = match output_device {
+                None => OutputStream::try_default().map_err(|e| {
+                    anyhow::anyhow!(
+                        "Could not open the default audio output device ({e}). \
+                         Choose one with --output-device (see `test-sound --list-devices`)."
+                    )
+                })?,
+                Some(name) => {
+                    let device = rodio::cpal::default_host()
+                        .output_devices()
+                        .context("enumerating audio output devices")?
+                        .find(|device| device.name().map(|n| n == name).unwrap_or(false))
+                        .ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "Audio output device `{name}` was not found \
+                                 (see `test-sound --list-devices`)."
+                            )
+                        })?;
+                    OutputStream::try_from_device(&device)
+                        .with_context(|| format!("opening audio output device `{name}`"))?
+                }
+            };
 
-            return Self {
+            Ok(Self {
                 #[allow(clippy::arc_with_non_send_sync)]
-                stream: Arc::new(stream.0),
-                stream_handle,
-            };
+                stream: Some(Arc::new(stream.0)),
+                output: AudioOutput::Device(Arc::new(stream.1)),
+                volume,
+                ptt: None,
+            })
         }
 
         #[cfg(not(feature = "audio"))]
         {
-            Self {}
+            if output_device.is_some() {
+                warn!("'audio' feature is disabled in this Cargo build. Ignoring --output-device.");
+            }
+            Ok(Self {
+                output: AudioOutput::Disabled,
+                volume,
+                ptt: None,
+            })
         }
     }
 
-    #[cfg(feature = "audio")]
-    pub fn play_gap(&self, dot_duration: u32, rts_port: Option<&str>) {
-        let tones = vec![(0.0, dot_duration)];
-        let sink = Sink::try_new(&self.stream_handle).unwrap();
-        let _ = play_morse_code(tones, &sink, rts_port);
-        sink.sleep_until_end();
+    /// Creates a player without sound, for keying other outputs (GPIO
+    /// pins, ...) where no audio device may be present.
+    pub fn silent(volume: f32) -> Self {
+        Self {
+            #[cfg(feature = "audio")]
+            stream: None,
+            output: AudioOutput::Disabled,
+            volume,
+            ptt: None,
+        }
     }
 
-    #[cfg(not(feature = "audio"))]
-    pub fn play_gap(&self, _dot_duration: u32, rts_port: Option<&str>) {
-        error!("'audio' feature is disabled in this Cargo build. Program cannot play audio.");
+    /// Sounds every tone on a piezo buzzer on a GPIO pin (instead of
+    /// the sound device), with PWM at the frequency of the tone.
+    pub fn open_buzzer(
+        pin_number: u8,
+        output: &GpioOutput,
+        tone_freq: f32,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            #[cfg(feature = "audio")]
+            stream: None,
+            output: AudioOutput::Buzzer(GpioBuzzer::new(pin_number, output, tone_freq)?),
+            volume: 1.0,
+            ptt: None,
+        })
     }
 
-    #[cfg(feature = "audio")]
-    pub fn play_nonblocking_tone(&self, dot_duration: u32, tone_freq: f32, rts_port: Option<&str>) {
-        // clone the port name into an owned String so it can live in the 'static thread
-        let owned_rts: Option<String> = rts_port.map(|s| s.to_string());
-        let stream_handle = self.stream_handle.clone();
+    /// Creates a PipeWire source node named `node_name` and plays
+    /// every tone into it (instead of the sound device) at `volume`.
+    #[cfg(target_os = "linux")]
+    #[cfg(feature = "pipewire")]
+    pub fn open_pipewire(node_name: &str, volume: f32) -> anyhow::Result<Self> {
+        Ok(Self {
+            #[cfg(feature = "audio")]
+            stream: None,
+            output: AudioOutput::PipeWire(Arc::new(PipewireSource::new(node_name)?)),
+            volume,
+            ptt: None,
+        })
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "pipewire")))]
+    pub fn open_pipewire(_node_name: &str, _volume: f32) -> anyhow::Result<Self> {
+        anyhow::bail!(
+            "'pipewire' feature is disabled in this Cargo build. Cannot create a PipeWire node."
+        )
+    }
+
+    /// Keys `ptt` (e.g. RTS on a serial port) while transmitting.
+    pub fn set_ptt(&mut self, ptt: Option<Ptt>) {
+        self.ptt = ptt;
+    }
+
+    /// Takes the PTT away from the player (e.g. to key it along with a
+    /// paddle keyer).
+    pub fn take_ptt(&mut self) -> Option<Ptt> {
+        self.ptt.take()
+    }
+
+    /// Keys PTT (if any) until the returned hold is dropped.
+    pub fn transmit(&self) -> anyhow::Result<Option<PttHold>> {
+        self.ptt.as_ref().map(Ptt::transmit).transpose()
+    }
+
+    fn play_tones(&self, tones: Vec<(f32, u32)>) {
+        let result = self
+            .transmit()
+            .and_then(|_ptt| self.output.play(tones, self.volume));
+        if let Err(e) = result {
+            error!("{e:#}");
+        }
+    }
+
+    /// Starts a sidetone at `tone_freq`, silent until it is keyed.
+    pub fn sidetone(&self, tone_freq: f32) -> anyhow::Result<Sidetone> {
+        let gate = Arc::new(AtomicBool::new(false));
+        match &self.output {
+            #[cfg(feature = "audio")]
+            AudioOutput::Device(stream_handle) => {
+                let sink = Sink::try_new(stream_handle)?;
+                sink.append(GatedTone {
+                    freq: tone_freq,
+                    sample_rate: SAMPLE_RATE,
+                    gain: self.volume,
+                    gate: gate.clone(),
+                    amplitude: 0.0,
+                    phase: 0.0,
+                });
+                Ok(Sidetone::Sound { gate, _sink: sink })
+            }
+            #[cfg(target_os = "linux")]
+            #[cfg(feature = "pipewire")]
+            AudioOutput::PipeWire(_) => {
+                anyhow::bail!("Sidetone needs a sound device, it cannot play into a PipeWire node.")
+            }
+            AudioOutput::Buzzer(buzzer) => {
+                buzzer.set_frequency(tone_freq)?;
+                Ok(Sidetone::Buzzer(buzzer.clone()))
+            }
+            AudioOutput::Disabled => {
+                let _ = (tone_freq, gate);
+                anyhow::bail!(
+                    "'audio' feature is disabled in this Cargo build. Program cannot play sidetone."
+                )
+            }
+        }
+    }
+
+    /// Plays silence, without keying PTT.
+    pub fn play_gap(&self, dot_duration: u32) {
+        if let Err(e) = self.output.play(vec![(0.0, dot_duration)], self.volume) {
+            error!("{e:#}");
+        }
+    }
+
+    pub fn play_nonblocking_tone(&self, dot_duration: u32, tone_freq: f32) {
+        let ptt = match self.transmit() {
+            Ok(ptt) => ptt,
+            Err(e) => {
+                error!("{e:#}");
+                return;
+            }
+        };
+        let output = self.output.clone();
+        let volume = self.volume;
 
         std::thread::spawn(move || {
             let tones = vec![(tone_freq, dot_duration)];
-            let sink = Sink::try_new(&stream_handle).unwrap();
-
-            // pass a `&str` into play_morse_code by calling `.as_deref()` on the owned String
-            play_morse_code(tones, &sink, owned_rts.as_deref()).unwrap();
-            sink.sleep_until_end();
+            if let Err(e) = output.play(tones, volume) {
+                error!("{e:#}");
+            }
+            drop(ptt);
         });
     }
 
-    #[cfg(not(feature = "audio"))]
-    pub fn play_nonblocking_tone(
-        &self,
-        _dot_duration: u32,
-        _tone_freq: f32,
-        rts_port: Option<&str>,
-    ) {
-        error!("Error: Audio feature is disabled. Cannot play non-blocking tone.");
+    pub fn play_morse(&self, message: &str, dot_duration: u32, tone_freq: f32) {
+        let tones = morse_to_tones(message, dot_duration, tone_freq);
+        self.play_tones(tones);
     }
 
-    #[cfg(feature = "audio")]
-    pub fn play_morse(
+    pub fn play(&self, message: &str, dot_duration: u32, tone_freq: f32) {
+        let tones = encode_morse(message, dot_duration, tone_freq);
+        self.play_tones(tones);
+    }
+
+    /// Plays the text message like `play`, but stops after the current
+    /// character once `abort` is set.
+    pub fn play_abortable(
         &self,
         message: &str,
         dot_duration: u32,
         tone_freq: f32,
-        rts_port: Option<&str>,
+        abort: &AtomicBool,
     ) {
-        let sink = Sink::try_new(&self.stream_handle).unwrap();
-        let tones = morse_to_tones(message, dot_duration, tone_freq);
-        let _ = play_morse_code(tones, &sink, rts_port);
-        sink.sleep_until_end();
-    }
-
-    #[cfg(feature = "audio")]
-    pub fn play(&self, message: &str, dot_duration: u32, tone_freq: f32, rts_port: Option<&str>) {
-        let sink = Sink::try_new(&self.stream_handle).unwrap();
         let tones = encode_morse(message, dot_duration, tone_freq);
-        let _ = play_morse_code(tones, &sink, rts_port);
-        sink.sleep_until_end();
+        let result = self.transmit().and_then(|_ptt| {
+            for character in split_characters(tones) {
+                if abort.load(Ordering::SeqCst) {
+                    break;
+                }
+                self.output.play(character, self.volume)?;
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            error!("{e:#}");
+        }
     }
 
-    #[cfg(not(feature = "audio"))]
-    pub fn play(
-        &self,
-        _message: &str,
-        _dot_duration: u32,
-        _tone_freq: f32,
-        rts_port: Option<&str>,
-    ) {
-        error!("Error: Audio feature is disabled. Cannot play Morse code.");
+    /// Play pre-rendered interleaved samples (e.g. the output of the mixer).
+    pub fn play_samples(&self, channels: u16, sample_rate: u32, samples: Vec<f32>) {
+        let samples = samples
+            .into_iter()
+            .map(|s| s * self.volume)
+            .collect::<Vec<_>>();
+        let _ptt = match self.transmit() {
+            Ok(ptt) => ptt,
+            Err(e) => {
+                error!("{e:#}");
+                return;
+            }
+        };
+        match &self.output {
+            #[cfg(feature = "audio")]
+            AudioOutput::Device(stream_handle) => {
+                let sink = match Sink::try_new(stream_handle) {
+                    Ok(sink) => sink,
+                    Err(e) => {
+                        error!("{e:#}");
+                        return;
+                    }
+                };
+                sink.append(rodio::buffer::SamplesBuffer::new(
+                    channels,
+                    sample_rate,
+                    samples,
+                ));
+                sink.sleep_until_end();
+            }
+            #[cfg(target_os = "linux")]
+            #[cfg(feature = "pipewire")]
+            AudioOutput::PipeWire(source) => {
+                if sample_rate != SAMPLE_RATE {
+                    error!("PipeWire output only supports {SAMPLE_RATE}Hz audio.");
+                    return;
+                }
+                // The PipeWire node is mono, downmix all channels:
+                let mono = samples
+                    .chunks(channels as usize)
+                    .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+                    .collect();
+                source.play(mono);
+            }
+            AudioOutput::Buzzer(_) => {
+                error!("A GPIO buzzer cannot play mixed audio.");
+            }
+            AudioOutput::Disabled => {
+                let _ = (channels, sample_rate, samples);
+                error!("Error: Audio feature is disabled. Cannot play mixed audio.");
+            }
+        }
     }
 
-    #[cfg(not(feature = "audio"))]
-    pub fn play_morse(
-        &self,
-        _message: &str,
-        _dot_duration: u32,
-        _tone_freq: f32,
-        rts_port: Option<&str>,
-    ) {
-        error!("Error: Audio feature is disabled. Cannot play Morse code.");
+    /// Keys the output line (e.g. a serial DTR line) with the morse
+    /// encoded message, keying PTT while keying.
+    pub fn key_morse(&self, message: &str, dot_duration: u32, key: &mut dyn Key) {
+        let tones = morse_to_tones(message, dot_duration, 333.); //frequency is unused but must be >0
+        self.key_tones(tones, key);
     }
 
-    #[cfg(feature = "gpio")]
-    pub fn gpio_morse(&self, message: &str, dot_duration: u32, pin_number: u8) {
-        let tones = morse_to_tones(message, dot_duration, 333.); //frequncy is unused but must be >0
-        gpio_morse_code(tones, pin_number);
+    /// Keys the output line (e.g. a serial DTR line) with the text
+    /// message, keying PTT while keying.
+    pub fn key(&self, message: &str, dot_duration: u32, key: &mut dyn Key) {
+        let tones = encode_morse(message, dot_duration, 333.); //frequency is unused but must be >0
+        self.key_tones(tones, key);
     }
 
-    #[cfg(feature = "gpio")]
-    pub fn gpio(&self, message: &str, dot_duration: u32, pin_number: u8) {
-        let tones = encode_morse(message, dot_duration, 333.); //frequency is unused but must be >0
-        gpio_morse_code(tones, pin_number);
+    /// Waits with the key up, without keying PTT.
+    pub fn key_gap(&self, dot_duration: u32, key: &mut dyn Key) {
+        if let Err(e) = key_morse_code(vec![(0.0, dot_duration)], key) {
+            error!("{e:#}");
+        }
     }
 
-    #[cfg(not(feature = "gpio"))]
-    pub fn gpio_morse(&self, _message: &str, _dot_duration: u32, _gpio_pin: u8) {
-        error!("Error: GPIO feature is disabled. Cannot play Morse code via GPIO.");
+    /// Keys the text message like `key`, but stops after the current
+    /// character once `abort` is set.
+    pub fn key_abortable(
+        &self,
+        message: &str,
+        dot_duration: u32,
+        key: &mut dyn Key,
+        abort: &AtomicBool,
+    ) {
+        let tones = encode_morse(message, dot_duration, 333.); //frequency is unused but must be >0
+        let result = self.transmit().and_then(|_ptt| {
+            for character in split_characters(tones) {
+                if abort.load(Ordering::SeqCst) {
+                    break;
+                }
+                key_morse_code(character, key)?;
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            error!("{e:#}");
+        }
     }
 
-    #[cfg(not(feature = "gpio"))]
-    pub fn gpio(&self, _message: &str, _dot_duration: u32, _gpio_pin: u8) {
-        error!("Error: GPIO feature is disabled. Cannot perform GPIO operations.");
+    /// Sends the text message on `output`, stopping after the current
+    /// character once `abort` is set.
+    pub fn send_abortable(
+        &self,
+        message: &str,
+        dot_duration: u32,
+        tone_freq: f32,
+        output: &mut CwOutput,
+        abort: &AtomicBool,
+    ) {
+        match output {
+            CwOutput::Sound => self.play_abortable(message, dot_duration, tone_freq, abort),
+            CwOutput::Key(key) => self.key_abortable(message, dot_duration, key.as_mut(), abort),
+        }
     }
 
-    #[cfg(feature = "gpio")]
-    pub fn gpio_gap(&self, dot_duration: u32, pin_number: u8) {
-        let mut pin = rppal::gpio::Gpio::new()
-            .expect("Failed to access GPIO")
-            .get(pin_number)
-            .expect("Failed to get GPIO pin")
-            .into_output();
-        pin.set_low();
-        sleep(Duration::from_millis(dot_duration.into()));
+    /// Waits on `output` with the key up, without keying PTT.
+    pub fn send_gap(&self, dot_duration: u32, output: &mut CwOutput) {
+        match output {
+            CwOutput::Sound => self.play_gap(dot_duration),
+            CwOutput::Key(key) => self.key_gap(dot_duration, key.as_mut()),
+        }
     }
 
-    #[cfg(not(feature = "gpio"))]
-    pub fn gpio_gap(&self, _dot_duration: u32, _gpio_pin: u8) {
-        error!("Error: GPIO feature is disabled. Cannot perform GPIO gap.");
+    fn key_tones(&self, tones: Vec<(f32, u32)>, key: &mut dyn Key) {
+        let result = self.transmit().and_then(|_ptt| key_morse_code(tones, key));
+        if let Err(e) = result {
+            error!("{e:#}");
+        }
     }
 }
 
@@ -454,4 +862,17 @@ mod tests {
             ".... . .-.. .-.. --- / .-- --- .-. .-.. -.. / .---- ..--- ...-- .-.-.- / .... --- .-- / .- .-. . / -.-- --- ..- ..--.."
         );
     }
+
+    #[test]
+    fn test_split_characters() {
+        let characters = split_characters(encode_morse("AN E", 10, 600.0));
+        let elements: Vec<usize> = characters
+            .iter()
+            .map(|c| c.iter().filter(|(freq, _)| *freq > 0.0).count())
+            .collect();
+        assert_eq!(elements, vec![2, 2, 1]);
+        // The word gap stays with the character before it:
+        let gap: u32 = characters[1].iter().skip(3).map(|(_, d)| d).sum();
+        assert!(gap >= 70, "word gap {gap}ms");
+    }
 }
@@ -1,20 +1,24 @@
 #[allow(unused_imports)]
+use crate::cwlog::Recorder;
+#[allow(unused_imports)]
 use crate::filter::*;
 #[allow(unused_imports)]
 use crate::message::Message;
 #[allow(unused_imports)]
-use crate::morse::text_to_morse;
+use crate::morse::{get_decoder, text_to_morse, SAMPLE_RATE};
 #[cfg(target_os = "linux")]
 #[cfg(feature = "pipewire")]
 use crate::pipewire::spa::pod::Pod;
 #[allow(unused_imports)]
 use crate::prelude::*;
 #[allow(unused_imports)]
+#[cfg(feature = "pipewire")]
+use crate::rigctld::RigStatus;
+use crate::rigctld::Rigctld;
+#[allow(unused_imports)]
 use crate::term::log_message;
 #[allow(unused_imports)]
 use chrono::Local;
-#[allow(unused_imports)]
-use morse_codec::decoder::Decoder;
 #[cfg(target_os = "linux")]
 #[cfg(feature = "pipewire")]
 use pipewire as pw;
@@ -27,9 +31,13 @@ use pw::{context::Context, main_loop::MainLoop, spa};
 #[allow(unused_imports)]
 use regex::Regex;
 #[allow(unused_imports)]
+use std::collections::VecDeque;
+#[allow(unused_imports)]
 use std::process::Command;
 #[allow(unused_imports)]
-use std::time::Instant;
+use std::sync::{Arc, Mutex};
+#[allow(unused_imports)]
+use std::time::{Duration, Instant};
 
 #[cfg(target_os = "linux")]
 #[cfg(feature = "pipewire")]
@@ -76,7 +84,7 @@ pub fn ensure_pipewire() {
                 }
             }
 
-            std::process::exit(1);
+            crate::shutdown::exit(1);
         }
     }
 }
@@ -88,9 +96,11 @@ pub fn listen(
     _threshold: f32,
     _dot_duration: u32,
     _output_morse: bool,
+    _rig: Option<Rigctld>,
+    _record: Option<Recorder>,
 ) -> Result<(), std::io::Error> {
     error!("listen feature not implemented on windows");
-    return Ok(());
+    Ok(())
 }
 
 #[cfg(target_os = "linux")]
@@ -101,9 +111,11 @@ pub fn listen(
     _threshold: f32,
     _dot_duration: u32,
     _output_morse: bool,
+    _rig: Option<Rigctld>,
+    _record: Option<Recorder>,
 ) -> Result<(), std::io::Error> {
     error!("'pipewire' feature is disabled in the Cargo build. Program cannot receive audio.");
-    return Ok(());
+    Ok(())
 }
 
 #[cfg(target_os = "linux")]
@@ -114,7 +126,12 @@ pub fn listen(
     threshold: f32,
     dot_duration: u32,
     output_morse: bool,
+    rig: Option<Rigctld>,
+    record: Option<Recorder>,
 ) -> Result<(), pipewire::Error> {
+    // Don't wait on rigctld or the recording file in the audio callback:
+    let rig = rig.map(RigStatus::spawn);
+    let mut record = record.map(Recorder::spawn);
     pw::init();
     let mainloop = MainLoop::new(None)?;
     let context = Context::new(&mainloop)?;
@@ -135,9 +152,8 @@ pub fn listen(
 
     let stream = pw::stream::Stream::new(&core, "audio-capture", props)?;
 
-    let mut decoder = Decoder::<9999>::new()
-        .with_reference_short_ms(dot_duration as u16)
-        .build();
+    let mut decoder = get_decoder(dot_duration);
+    let start = Instant::now();
     let mut last_signal_change = Instant::now();
     let mut last_signal_state = false;
     let whitespace_regex = Regex::new(r"\s+").unwrap();
@@ -207,8 +223,16 @@ pub fn listen(
 
                         // Detect message characters:
                         if tone_detected != last_signal_state {
+                            // Record the edge (until recording stops):
+                            let at = now.duration_since(start);
+                            let stopped = record
+                                .as_ref()
+                                .is_some_and(|record| !record.edge(at, tone_detected));
+                            if stopped {
+                                record = None;
+                            }
                             decoder.signal_event(duration as u16, last_signal_state);
-                            let mut msg = decoder.message.as_str().to_string();
+                            let mut msg = decoder.message().to_string();
                             msg = whitespace_regex.replace_all(&msg, " ").to_string();
 
                             if !msg.is_empty() {
@@ -233,13 +257,13 @@ pub fn listen(
                         if duration > timeout_duration {
                             last_signal_change = now;
                             last_signal_state = false;
-                            let mut msg = decoder.message.as_str().to_string();
+                            let mut msg = decoder.message().to_string();
                             msg = whitespace_regex.replace_all(&msg, " ").to_string();
 
                             if !msg.is_empty() {
                                 decoder.signal_event_end(false);
                                 decoder.signal_event_end(true);
-                                msg = decoder.message.as_str().to_string();
+                                msg = decoder.message().to_string();
                                 msg = whitespace_regex.replace_all(&msg, " ").to_string();
 
                                 clear_screen();
@@ -254,6 +278,7 @@ pub fn listen(
                                 let mut m = Message {
                                     timestamp: timestamp.clone(),
                                     content: msg.clone(),
+                                    rig: rig.as_ref().and_then(RigStatus::get),
                                 };
                                 if output_morse {
                                     m.content = text_to_morse(&m.content);
@@ -263,7 +288,7 @@ pub fn listen(
                                 // Push the complete message into the log
                                 user_data.message_log.push(m);
                                 // Clear the decoder to prepare for a new message:
-                                decoder.message.clear();
+                                decoder.clear_message();
                             }
                         }
                     }
@@ -301,3 +326,324 @@ pub fn listen(
     mainloop.run();
     Ok(())
 }
+
+/// Message telling the PipeWire thread of a `PipewireSource` to quit
+#[cfg(target_os = "linux")]
+#[cfg(feature = "pipewire")]
+struct Terminate;
+
+/// A named PipeWire source node that plays back generated audio.
+///
+/// The node is not connected to anything by itself: other applications
+/// (fldigi, OBS, a web conferencing app, ...) select it as their input.
+#[cfg(target_os = "linux")]
+#[cfg(feature = "pipewire")]
+pub struct PipewireSource {
+    queue: Arc<Mutex<VecDeque<f32>>>, // Mono samples waiting to be played
+    sender: pw::channel::Sender<Terminate>,
+    thread: Mutex<Option<std::thread::JoinHandle<()>>>,
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(feature = "pipewire")]
+impl PipewireSource {
+    /// Creates the source node and runs its PipeWire main loop in a
+    /// background thread.
+    pub fn new(node_name: &str) -> anyhow::Result<Self> {
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        let (sender, receiver) = pw::channel::channel();
+        let (ready_sender, ready_receiver) = std::sync::mpsc::channel();
+        let thread = {
+            let queue = queue.clone();
+            let node_name = node_name.to_string();
+            std::thread::spawn(move || {
+                if let Err(e) = run_source(&node_name, queue, receiver, &ready_sender) {
+                    let _ = ready_sender.send(Err(e.to_string()));
+                }
+            })
+        };
+        match ready_receiver.recv() {
+            Ok(Ok(())) => {
+                info!("Created PipeWire source node `{node_name}`");
+                Ok(Self {
+                    queue,
+                    sender,
+                    thread: Mutex::new(Some(thread)),
+                })
+            }
+            Ok(Err(e)) => anyhow::bail!("creating PipeWire source node `{node_name}`: {e}"),
+            Err(_) => anyhow::bail!("PipeWire thread exited before creating `{node_name}`"),
+        }
+    }
+
+    /// Queues mono samples for playback without waiting for them.
+    pub fn enqueue(&self, samples: Vec<f32>) {
+        self.queue.lock().unwrap().extend(samples);
+    }
+
+    /// Queues mono samples and blocks until they have been played.
+    ///
+    /// If no application is consuming the node, the samples are
+    /// discarded once their playback time has passed.
+    pub fn play(&self, samples: Vec<f32>) {
+        self.enqueue(samples);
+        let queued = self.queue.lock().unwrap().len();
+        let deadline = Instant::now()
+            + Duration::from_secs_f64(queued as f64 / SAMPLE_RATE as f64)
+            + Duration::from_millis(500);
+        loop {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.is_empty() {
+                break;
+            }
+            if Instant::now() > deadline {
+                debug!("Nothing is consuming the PipeWire source node, discarding audio.");
+                queue.clear();
+                break;
+            }
+            drop(queue);
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(feature = "pipewire")]
+impl Drop for PipewireSource {
+    fn drop(&mut self) {
+        let _ = self.sender.send(Terminate);
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(feature = "pipewire")]
+fn run_source(
+    node_name: &str,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    receiver: pw::channel::Receiver<Terminate>,
+    ready: &std::sync::mpsc::Sender<Result<(), String>>,
+) -> Result<(), pipewire::Error> {
+    pw::init();
+    let mainloop = MainLoop::new(None)?;
+    let context = Context::new(&mainloop)?;
+    let core = context.connect(None)?;
+
+    // Quit the main loop when the PipewireSource is dropped:
+    let _receiver = receiver.attach(mainloop.loop_(), {
+        let mainloop = mainloop.clone();
+        move |_| mainloop.quit()
+    });
+
+    let props = properties!(
+        *pw::keys::MEDIA_TYPE => "Audio",
+        *pw::keys::MEDIA_CATEGORY => "Playback",
+        *pw::keys::MEDIA_ROLE => "Communication",
+        *pw::keys::MEDIA_CLASS => "Audio/Source",
+        *pw::keys::NODE_NAME => node_name,
+        *pw::keys::NODE_DESCRIPTION => node_name
+    );
+
+    let stream = pw::stream::Stream::new(&core, node_name, props)?;
+
+    let _listener = stream
+        .add_local_listener_with_user_data(queue)
+        .process(|stream, queue| match stream.dequeue_buffer() {
+            None => debug!("Out of buffers"),
+            Some(mut buffer) => {
+                let datas = buffer.datas_mut();
+                if datas.is_empty() {
+                    return;
+                }
+                let stride = std::mem::size_of::<f32>();
+                let data = &mut datas[0];
+                let n_frames = if let Some(slice) = data.data() {
+                    let mut queue = queue.lock().unwrap();
+                    for frame in slice.chunks_exact_mut(stride) {
+                        // Play silence while there is nothing to send:
+                        let sample = queue.pop_front().unwrap_or(0.0);
+                        frame.copy_from_slice(&sample.to_le_bytes());
+                    }
+                    slice.len() / stride
+                } else {
+                    0
+                };
+                let chunk = data.chunk_mut();
+                *chunk.offset_mut() = 0;
+                *chunk.stride_mut() = stride as _;
+                *chunk.size_mut() = (stride * n_frames) as _;
+            }
+        })
+        .register()?;
+
+    let mut audio_info = spa::param::audio::AudioInfoRaw::new();
+    audio_info.set_format(spa::param::audio::AudioFormat::F32LE);
+    audio_info.set_rate(SAMPLE_RATE);
+    audio_info.set_channels(1);
+    let obj = pw::spa::pod::Object {
+        type_: pw::spa::utils::SpaTypes::ObjectParamFormat.as_raw(),
+        id: pw::spa::param::ParamType::EnumFormat.as_raw(),
+        properties: audio_info.into(),
+    };
+    let values: Vec<u8> = pw::spa::pod::serialize::PodSerializer::serialize(
+        std::io::Cursor::new(Vec::new()),
+        &pw::spa::pod::Value::Object(obj),
+    )
+    .unwrap()
+    .0
+    .into_inner();
+
+    let mut params = [Pod::from_bytes(&values).unwrap()];
+
+    // No AUTOCONNECT: other applications pick this node as their input.
+    stream.connect(
+        spa::utils::Direction::Output,
+        None,
+        pw::stream::StreamFlags::MAP_BUFFERS | pw::stream::StreamFlags::RT_PROCESS,
+        &mut params,
+    )?;
+
+    let _ = ready.send(Ok(()));
+    mainloop.run();
+    Ok(())
+}
+
+/// Mono audio recorded from the default monitor by `capture`
+#[allow(dead_code)]
+pub struct Capture {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub started: Option<Instant>, // When the first captured sample was recorded
+}
+
+#[cfg(not(all(target_os = "linux", feature = "pipewire")))]
+pub fn capture(
+    _duration: Duration,
+    _ready: std::sync::mpsc::Sender<()>,
+) -> anyhow::Result<Capture> {
+    anyhow::bail!(
+        "'pipewire' feature is disabled in the Cargo build. Program cannot capture audio."
+    )
+}
+
+/// Records the default system audio monitor (the same source used by
+/// `listen`) for `duration`, downmixed to mono. A message is sent on
+/// `ready` once the first samples arrive.
+#[cfg(target_os = "linux")]
+#[cfg(feature = "pipewire")]
+pub fn capture(duration: Duration, ready: std::sync::mpsc::Sender<()>) -> anyhow::Result<Capture> {
+    struct CaptureData {
+        format: spa::param::audio::AudioInfoRaw,
+        capture: Arc<Mutex<Capture>>,
+        ready: std::sync::mpsc::Sender<()>,
+    }
+
+    pw::init();
+    let mainloop = MainLoop::new(None)?;
+    let context = Context::new(&mainloop)?;
+    let core = context.connect(None)?;
+
+    let result = Arc::new(Mutex::new(Capture {
+        samples: Vec::new(),
+        sample_rate: 0,
+        started: None,
+    }));
+    let data = CaptureData {
+        format: Default::default(),
+        capture: result.clone(),
+        ready,
+    };
+
+    let props = properties!(
+        *pw::keys::MEDIA_TYPE => "Audio",
+        *pw::keys::MEDIA_CATEGORY => "Capture",
+        *pw::keys::MEDIA_ROLE => "Communication",
+        *pw::keys::STREAM_CAPTURE_SINK => "true"
+    );
+
+    let stream = pw::stream::Stream::new(&core, "audio-loopback", props)?;
+
+    let _listener = stream
+        .add_local_listener_with_user_data(data)
+        .param_changed(|_, user_data, id, param| {
+            let Some(param) = param else {
+                return;
+            };
+            if id != pw::spa::param::ParamType::Format.as_raw() {
+                return;
+            }
+            user_data.format.parse(param).unwrap();
+            user_data.capture.lock().unwrap().sample_rate = user_data.format.rate();
+        })
+        .process(|stream, user_data| match stream.dequeue_buffer() {
+            None => debug!("Out of buffers"),
+            Some(mut buffer) => {
+                let datas = buffer.datas_mut();
+                if datas.is_empty() {
+                    return;
+                }
+                let n_channels = user_data.format.channels().max(1) as usize;
+                if let Some(samples) = datas[0].data() {
+                    let float_samples: &[f32] = bytemuck::cast_slice(samples);
+                    let mut capture = user_data.capture.lock().unwrap();
+                    if capture.started.is_none() {
+                        let frames = float_samples.len() / n_channels;
+                        let rate = user_data.format.rate().max(1);
+                        capture.started = Instant::now()
+                            .checked_sub(Duration::from_secs_f64(frames as f64 / rate as f64));
+                        let _ = user_data.ready.send(());
+                    }
+                    capture.samples.extend(
+                        float_samples
+                            .chunks(n_channels)
+                            .map(|frame| frame.iter().sum::<f32>() / n_channels as f32),
+                    );
+                }
+            }
+        })
+        .register()?;
+
+    let mut audio_info = spa::param::audio::AudioInfoRaw::new();
+    audio_info.set_format(spa::param::audio::AudioFormat::F32LE);
+    let obj = pw::spa::pod::Object {
+        type_: pw::spa::utils::SpaTypes::ObjectParamFormat.as_raw(),
+        id: pw::spa::param::ParamType::EnumFormat.as_raw(),
+        properties: audio_info.into(),
+    };
+    let values: Vec<u8> = pw::spa::pod::serialize::PodSerializer::serialize(
+        std::io::Cursor::new(Vec::new()),
+        &pw::spa::pod::Value::Object(obj),
+    )
+    .unwrap()
+    .0
+    .into_inner();
+
+    let mut params = [Pod::from_bytes(&values).unwrap()];
+
+    stream.connect(
+        spa::utils::Direction::Input,
+        None,
+        pw::stream::StreamFlags::AUTOCONNECT
+            | pw::stream::StreamFlags::MAP_BUFFERS
+            | pw::stream::StreamFlags::RT_PROCESS,
+        &mut params,
+    )?;
+
+    // Stop recording after the requested duration:
+    let timer = mainloop.loop_().add_timer({
+        let mainloop = mainloop.clone();
+        move |_| mainloop.quit()
+    });
+    let _ = timer.update_timer(Some(duration), None);
+
+    mainloop.run();
+
+    let mut capture = result.lock().unwrap();
+    Ok(Capture {
+        samples: std::mem::take(&mut capture.samples),
+        sample_rate: capture.sample_rate,
+        started: capture.started,
+    })
+}
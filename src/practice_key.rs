@@ -0,0 +1,161 @@
+//! Keying CW from the computer keyboard: the spacebar is a straight
+//! key, and left/right Ctrl (or `[` and `]`) are the dit and dah
+//! paddles of the keyer. USB paddle adapters that emulate Ctrl keys
+//! (Vail, VBand) work the same way.
+use crate::decode::LiveDecoder;
+use crate::input::{receive_contacts, Contact, ContactEvent};
+use crate::key::Key;
+use crate::keyer::KeyerSettings;
+use crate::prelude::*;
+use anyhow::{bail, Context};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    ModifierKeyCode, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::execute;
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, supports_keyboard_enhancement};
+use std::io::stdout;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// Raw mode with key release reporting, restored when dropped
+struct KeyboardMode {
+    enhanced: bool,
+}
+
+impl KeyboardMode {
+    fn enable() -> anyhow::Result<Self> {
+        // Terminals only report key releases with the keyboard
+        // enhancement protocol (Windows always reports them):
+        let enhanced = supports_keyboard_enhancement().unwrap_or(false);
+        if !enhanced && !cfg!(windows) {
+            bail!(
+                "This terminal doesn't report key releases, which are needed to time the keying. \
+                 Try a terminal supporting the kitty keyboard protocol (kitty, foot, WezTerm, Ghostty, ...)."
+            );
+        }
+        enable_raw_mode().context("enabling terminal raw mode")?;
+        let mode = Self { enhanced };
+        if enhanced {
+            execute!(
+                stdout(),
+                PushKeyboardEnhancementFlags(
+                    KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                        | KeyboardEnhancementFlags::REPORT_ALL_KEYS_AS_ESCAPE_CODES
+                )
+            )
+            .context("enabling key release events")?;
+        }
+        Ok(mode)
+    }
+}
+
+impl Drop for KeyboardMode {
+    fn drop(&mut self) {
+        if self.enhanced {
+            let _ = execute!(stdout(), PopKeyboardEnhancementFlags);
+        }
+        let _ = disable_raw_mode();
+    }
+}
+
+/// Which contact a keyboard key is
+fn contact(code: KeyCode) -> Option<Contact> {
+    match code {
+        KeyCode::Char(' ') => Some(Contact::Straight),
+        KeyCode::Modifier(ModifierKeyCode::LeftControl) | KeyCode::Char('[') => Some(Contact::Dit),
+        KeyCode::Modifier(ModifierKeyCode::RightControl) | KeyCode::Char(']') => Some(Contact::Dah),
+        _ => None,
+    }
+}
+
+/// The contact a key press or release closes or opens, at `at`.
+fn contact_event(event: &KeyEvent, at: Duration) -> Option<ContactEvent> {
+    // Holding a key repeats its presses, ignore those:
+    match event.kind {
+        KeyEventKind::Repeat => None,
+        kind => contact(event.code).map(|contact| ContactEvent {
+            contact,
+            closed: kind == KeyEventKind::Press,
+            at,
+        }),
+    }
+}
+
+/// Keys `key` (sidetone, output lines, PTT, ...) from the keyboard and
+/// decodes what is sent, until Esc is pressed.
+pub fn run(settings: KeyerSettings, key: Box<dyn Key>, decoder: LiveDecoder) -> anyhow::Result<()> {
+    info!(
+        "Key with Space (straight key), or Left/Right Ctrl or [ and ] (paddles, see --keyer-mode) - Press Esc to stop."
+    );
+    let _mode = KeyboardMode::enable()?;
+
+    // Read the keyboard in a thread, so the keyer can time its elements
+    // while waiting for the next key:
+    let start = Instant::now();
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || loop {
+        let event = match event::read() {
+            Ok(Event::Key(event)) => event,
+            Ok(_) => continue,
+            Err(e) => {
+                let _ = sender.send(Err(anyhow::Error::new(e).context("reading the keyboard")));
+                return;
+            }
+        };
+        let stop = event.code == KeyCode::Esc
+            || (event.code == KeyCode::Char('c')
+                && event.modifiers.contains(KeyModifiers::CONTROL));
+        if stop {
+            return;
+        }
+        let Some(event) = contact_event(&event, start.elapsed()) else {
+            continue;
+        };
+        if sender.send(Ok(event)).is_err() {
+            return;
+        }
+    });
+
+    receive_contacts(receiver, || start.elapsed(), settings, key, decoder)?;
+    println!();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contact() {
+        assert_eq!(contact(KeyCode::Char(' ')), Some(Contact::Straight));
+        let left = KeyCode::Modifier(ModifierKeyCode::LeftControl);
+        let right = KeyCode::Modifier(ModifierKeyCode::RightControl);
+        assert_eq!(contact(left), Some(Contact::Dit));
+        assert_eq!(contact(KeyCode::Char('[')), Some(Contact::Dit));
+        assert_eq!(contact(right), Some(Contact::Dah));
+        assert_eq!(contact(KeyCode::Char(']')), Some(Contact::Dah));
+        assert_eq!(contact(KeyCode::Char('e')), None);
+        assert_eq!(contact(KeyCode::Enter), None);
+    }
+
+    #[test]
+    fn test_contact_events_follow_presses_and_releases() {
+        let event = |code, kind| KeyEvent::new_with_kind(code, KeyModifiers::NONE, kind);
+        let at = Duration::from_millis(120);
+        let space = KeyCode::Char(' ');
+
+        let pressed = contact_event(&event(space, KeyEventKind::Press), at).unwrap();
+        assert_eq!(
+            (pressed.contact, pressed.closed, pressed.at),
+            (Contact::Straight, true, at)
+        );
+        let released = contact_event(&event(KeyCode::Char('['), KeyEventKind::Release), at);
+        let released = released.unwrap();
+        assert_eq!((released.contact, released.closed), (Contact::Dit, false));
+
+        // Repeats while held and other keys are ignored:
+        assert!(contact_event(&event(space, KeyEventKind::Repeat), at).is_none());
+        assert!(contact_event(&event(KeyCode::Char('x'), KeyEventKind::Press), at).is_none());
+    }
+}
@@ -0,0 +1,280 @@
+use crate::key::Key;
+use crate::prelude::*;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{sleep, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Push-to-talk timing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PttTiming {
+    pub lead: Duration, // Delay between asserting PTT and the first element
+    pub tail: Duration, // Delay between the last element and releasing PTT
+    pub hang: Duration, // Extra time PTT stays keyed waiting for more to send
+}
+
+struct PttState {
+    key: Box<dyn Key>,
+    keyed: bool,
+    holds: usize,                  // Number of transmissions in progress
+    releases: u64,                 // Incremented whenever a transmission ends
+    last_release: Option<Instant>, // When the last transmission ended
+}
+
+impl PttState {
+    fn unkey(&mut self) {
+        if self.keyed {
+            if let Err(e) = self.key.set(false) {
+                error!("Failed to release PTT: {e:#}");
+            }
+            self.keyed = false;
+            debug!("PTT OFF");
+        }
+    }
+}
+
+/// Switches a transmitter through a PTT line (serial RTS, GPIO pin, ...)
+///
+/// PTT is asserted `lead` before the first element, and released
+/// `tail + hang` after the last one. Transmissions that start before
+/// then keep PTT keyed, so consecutive lines don't chatter the relay.
+pub struct Ptt {
+    state: Arc<Mutex<PttState>>,
+    timing: PttTiming,
+}
+
+impl Ptt {
+    pub fn new(key: Box<dyn Key>, timing: PttTiming) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(PttState {
+                key,
+                keyed: false,
+                holds: 0,
+                releases: 0,
+                last_release: None,
+            })),
+            timing,
+        }
+    }
+
+    /// Keys PTT for a transmission, waiting for the lead time if PTT
+    /// was not already keyed. PTT stays keyed while the returned hold
+    /// is alive.
+    pub fn transmit(&self) -> anyhow::Result<PttHold> {
+        let (hold, newly_keyed) = self.hold()?;
+        if newly_keyed {
+            sleep(self.timing.lead);
+        }
+        Ok(hold)
+    }
+
+    /// Keys PTT like `transmit`, without waiting for the lead time.
+    /// Also returns whether PTT was newly keyed.
+    fn hold(&self) -> anyhow::Result<(PttHold, bool)> {
+        let mut state = self.state.lock().unwrap();
+        let newly_keyed = !state.keyed;
+        if newly_keyed {
+            state.key.set(true)?;
+            state.keyed = true;
+            debug!("PTT ON");
+        }
+        state.holds += 1;
+        let hold = PttHold {
+            state: self.state.clone(),
+            release_delay: self.timing.tail + self.timing.hang,
+        };
+        Ok((hold, newly_keyed))
+    }
+}
+
+impl Drop for Ptt {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        if state.keyed {
+            // Don't cut off the end of the last transmission:
+            if let Some(last_release) = state.last_release {
+                sleep((last_release + self.timing.tail).saturating_duration_since(Instant::now()));
+            }
+            state.unkey();
+        }
+    }
+}
+
+/// Keeps PTT keyed until dropped (see `Ptt::transmit`)
+pub struct PttHold {
+    state: Arc<Mutex<PttState>>,
+    release_delay: Duration,
+}
+
+impl Drop for PttHold {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.holds -= 1;
+        state.releases += 1;
+        state.last_release = Some(Instant::now());
+        if state.holds > 0 {
+            return;
+        }
+        if self.release_delay.is_zero() {
+            state.unkey();
+            return;
+        }
+        // Release PTT later, unless another transmission starts first:
+        let releases = state.releases;
+        let shared = self.state.clone();
+        let delay = self.release_delay;
+        std::thread::spawn(move || {
+            sleep(delay);
+            let mut state = shared.lock().unwrap();
+            if state.holds == 0 && state.releases == releases {
+                state.unkey();
+            }
+        });
+    }
+}
+
+/// Switches PTT along with the outputs of live keying (e.g. from a
+/// paddle)
+///
+/// PTT is asserted as soon as the key goes down, and the outputs follow
+/// the key the lead time later, so nothing waits on the lead. PTT is
+/// released once the outputs have been up for the tail and hang time.
+pub struct PttKey {
+    changes: Option<mpsc::Sender<(bool, Instant)>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl PttKey {
+    pub fn new(ptt: Ptt, mut outputs: Box<dyn Key>) -> Self {
+        let (sender, changes) = mpsc::channel::<(bool, Instant)>();
+        let worker = std::thread::spawn(move || {
+            let mut hold = None;
+            for (down, at) in changes {
+                if down && hold.is_none() {
+                    match ptt.hold() {
+                        Ok((ptt_hold, _)) => hold = Some(ptt_hold),
+                        Err(e) => error!("Failed to key PTT: {e:#}"),
+                    }
+                }
+                sleep((at + ptt.timing.lead).saturating_duration_since(Instant::now()));
+                if let Err(e) = outputs.set(down) {
+                    error!("{e:#}");
+                }
+                if !down {
+                    hold = None;
+                }
+            }
+            if hold.is_some() {
+                let _ = outputs.set(false);
+                drop(hold); // Before the Ptt
+            }
+        });
+        Self {
+            changes: Some(sender),
+            worker: Some(worker),
+        }
+    }
+}
+
+impl Key for PttKey {
+    fn set(&mut self, down: bool) -> anyhow::Result<()> {
+        let sent = self
+            .changes
+            .as_ref()
+            .map(|changes| changes.send((down, Instant::now())));
+        match sent {
+            Some(Ok(())) => Ok(()),
+            _ => anyhow::bail!("PTT keying stopped"),
+        }
+    }
+}
+
+impl Drop for PttKey {
+    fn drop(&mut self) {
+        // Let the outputs catch up, then release PTT:
+        self.changes = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct RecordingKey(Arc<Mutex<Vec<bool>>>);
+
+    impl Key for RecordingKey {
+        fn set(&mut self, down: bool) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(down);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_ptt_hang_time_spans_gaps() {
+        let key = RecordingKey::default();
+        let timing = PttTiming {
+            lead: Duration::from_millis(5),
+            tail: Duration::from_millis(10),
+            hang: Duration::from_millis(40),
+        };
+        let ptt = Ptt::new(Box::new(key.clone()), timing);
+
+        // Two transmissions separated by less than the hang time:
+        drop(ptt.transmit().unwrap());
+        sleep(Duration::from_millis(20));
+        drop(ptt.transmit().unwrap());
+        assert_eq!(*key.0.lock().unwrap(), vec![true]);
+
+        // PTT is released once the tail and hang time have passed:
+        sleep(Duration::from_millis(150));
+        assert_eq!(*key.0.lock().unwrap(), vec![true, false]);
+
+        // A later transmission keys PTT again, and dropping the Ptt releases it:
+        let hold = ptt.transmit().unwrap();
+        drop(hold);
+        drop(ptt);
+        assert_eq!(*key.0.lock().unwrap(), vec![true, false, true, false]);
+    }
+
+    #[test]
+    fn test_ptt_key_leads_the_outputs() {
+        type Log = Arc<Mutex<Vec<(&'static str, bool, Instant)>>>;
+        struct NamedKey(&'static str, Log);
+        impl Key for NamedKey {
+            fn set(&mut self, down: bool) -> anyhow::Result<()> {
+                self.1.lock().unwrap().push((self.0, down, Instant::now()));
+                Ok(())
+            }
+        }
+
+        let log = Log::default();
+        let timing = PttTiming {
+            lead: Duration::from_millis(30),
+            ..Default::default()
+        };
+        let ptt = Ptt::new(Box::new(NamedKey("ptt", log.clone())), timing);
+        let mut key = PttKey::new(ptt, Box::new(NamedKey("key", log.clone())));
+
+        // Keying doesn't wait for the lead time:
+        let start = Instant::now();
+        key.set(true).unwrap();
+        assert!(start.elapsed() < Duration::from_millis(10));
+        sleep(Duration::from_millis(60));
+        let released = Instant::now();
+        key.set(false).unwrap();
+        drop(key);
+
+        let log = log.lock().unwrap();
+        let changes: Vec<(&str, bool)> = log.iter().map(|&(name, down, _)| (name, down)).collect();
+        assert_eq!(
+            changes,
+            [("ptt", true), ("key", true), ("key", false), ("ptt", false)]
+        );
+        // The outputs follow the keying the lead time later:
+        assert!(log[1].2 - start >= Duration::from_millis(30));
+        assert!(log[2].2 - released >= Duration::from_millis(30));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::key::Key;
+use crate::prelude::*;
+use anyhow::{bail, Context};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Default Hamlib rigctld TCP port
+pub const DEFAULT_PORT: u16 = 4532;
+
+/// How often `RigStatus` asks rigctld for the frequency and mode
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A connection to a Hamlib `rigctld` daemon.
+///
+/// Commands are sent with the extended response protocol (`+` prefix),
+/// so every reply ends with an `RPRT <code>` line.
+pub struct Rigctld {
+    address: String,
+    writer: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl Rigctld {
+    /// Connects to `host[:port]` (the port defaults to 4532).
+    pub fn connect(address: &str) -> anyhow::Result<Self> {
+        let address = if address.contains(':') {
+            address.to_string()
+        } else {
+            format!("{address}:{DEFAULT_PORT}")
+        };
+        let writer = TcpStream::connect(&address)
+            .with_context(|| format!("connecting to rigctld at {address}"))?;
+        writer.set_read_timeout(Some(Duration::from_secs(5)))?;
+        writer.set_nodelay(true)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        debug!("Connected to rigctld at {address}");
+        Ok(Self {
+            address,
+            writer,
+            reader,
+        })
+    }
+
+    /// Sends one command and returns the `Key: value` pairs of the reply.
+    fn command(&mut self, command: &str) -> anyhow::Result<Vec<(String, String)>> {
+        trace!("rigctld <- {command}");
+        writeln!(self.writer, "+{command}")
+            .with_context(|| format!("sending to rigctld at {}", self.address))?;
+        let mut values = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                bail!("rigctld at {} closed the connection", self.address);
+            }
+            let line = line.trim_end();
+            trace!("rigctld -> {line}");
+            if let Some(code) = line.strip_prefix("RPRT ") {
+                return match code.trim() {
+                    "0" => Ok(values),
+                    code => bail!("rigctld command `{command}` failed (RPRT {code})"),
+                };
+            }
+            if let Some((key, value)) = line.split_once(": ") {
+                values.push((key.trim().to_string(), value.trim().to_string()));
+            }
+        }
+    }
+
+    pub fn set_ptt(&mut self, on: bool) -> anyhow::Result<()> {
+        self.command(if on { "T 1" } else { "T 0" })?;
+        Ok(())
+    }
+
+    /// Sets the speed of the rig's internal keyer.
+    pub fn set_keyer_speed(&mut self, wpm: u32) -> anyhow::Result<()> {
+        self.command(&format!("L KEYSPD {wpm}"))?;
+        Ok(())
+    }
+
+    /// Sends text through the rig's internal keyer.
+    pub fn send_morse(&mut self, text: &str) -> anyhow::Result<()> {
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !text.is_empty() {
+            self.command(&format!("b {text}"))?;
+        }
+        Ok(())
+    }
+}
+
+// Only used when receiving (from GPIO or PipeWire):
+#[allow(dead_code)]
+impl Rigctld {
+    fn get(&mut self, command: &str, key: &str) -> anyhow::Result<String> {
+        self.command(command)?
+            .into_iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value)
+            .with_context(|| format!("rigctld reply to `{command}` has no {key}"))
+    }
+
+    /// Frequency in Hz
+    pub fn frequency(&mut self) -> anyhow::Result<u64> {
+        let frequency = self.get("f", "Frequency")?;
+        // Some rigs report a fractional frequency:
+        frequency
+            .parse::<f64>()
+            .map(|f| f as u64)
+            .with_context(|| format!("invalid frequency `{frequency}`"))
+    }
+
+    /// Mode name (e.g. `CW`, `USB`)
+    pub fn mode(&mut self) -> anyhow::Result<String> {
+        self.get("m", "Mode")
+    }
+
+    /// Describes the current frequency and mode, e.g. `14.025000 MHz CW`.
+    pub fn status(&mut self) -> anyhow::Result<String> {
+        let frequency = self.frequency()?;
+        let mode = self.mode()?;
+        Ok(format!("{:.6} MHz {mode}", frequency as f64 / 1e6))
+    }
+}
+
+/// The rig frequency and mode, polled on a separate thread so it can be
+/// read without waiting on the network (e.g. from an audio callback).
+#[cfg_attr(not(feature = "pipewire"), allow(dead_code))]
+pub struct RigStatus {
+    status: Arc<Mutex<Option<String>>>,
+}
+
+#[cfg_attr(not(feature = "pipewire"), allow(dead_code))]
+impl RigStatus {
+    pub fn spawn(mut rig: Rigctld) -> Self {
+        let status = Arc::new(Mutex::new(None));
+        let latest = Arc::downgrade(&status);
+        std::thread::spawn(move || {
+            // Stops once the `RigStatus` is dropped:
+            while let Some(latest) = latest.upgrade() {
+                let status = rig.status().map_err(|e| warn!("{e:#}")).ok();
+                *latest.lock().unwrap() = status;
+                drop(latest);
+                std::thread::sleep(STATUS_INTERVAL);
+            }
+        });
+        Self { status }
+    }
+
+    /// The last status received, if any
+    pub fn get(&self) -> Option<String> {
+        self.status.lock().unwrap().clone()
+    }
+}
+
+/// PTT through rigctld
+impl Key for Rigctld {
+    fn set(&mut self, down: bool) -> anyhow::Result<()> {
+        self.set_ptt(down)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    /// Answers like rigctld with the extended response protocol and
+    /// records the commands it receives.
+    fn fake_rigctld() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let received = commands.clone();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            for line in BufReader::new(stream).lines() {
+                let command = line.unwrap().trim_start_matches('+').to_string();
+                let reply = match command.as_str() {
+                    "f" => "get_freq:\nFrequency: 14025000\nRPRT 0\n".to_string(),
+                    "m" => "get_mode:\nMode: CW\nPassband: 500\nRPRT 0\n".to_string(),
+                    "T 2" => "set_ptt: 2\nRPRT -1\n".to_string(),
+                    _ => format!("{command}:\nRPRT 0\n"),
+                };
+                received.lock().unwrap().push(command);
+                writer.write_all(reply.as_bytes()).unwrap();
+            }
+        });
+        (address, commands)
+    }
+
+    #[test]
+    fn test_rigctld_commands() {
+        let (address, commands) = fake_rigctld();
+        let mut rig = Rigctld::connect(&address).unwrap();
+        rig.set(true).unwrap();
+        rig.set(false).unwrap();
+        rig.send_morse("CQ  TEST\n").unwrap();
+        assert_eq!(rig.status().unwrap(), "14.025000 MHz CW");
+        assert!(rig.command("T 2").is_err());
+        assert_eq!(
+            *commands.lock().unwrap(),
+            vec!["T 1", "T 0", "b CQ TEST", "f", "m", "T 2"]
+        );
+    }
+
+    #[test]
+    fn test_rig_status_is_polled() {
+        let (address, _) = fake_rigctld();
+        let status = RigStatus::spawn(Rigctld::connect(&address).unwrap());
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while status.get().is_none() && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(status.get().as_deref(), Some("14.025000 MHz CW"));
+    }
+}
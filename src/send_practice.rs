@@ -0,0 +1,461 @@
+//! The sending trainer (`send-practice`): shows a target (a word, a
+//! callsign or a sentence) to key, decodes what is keyed, and grades it
+//! against the target: the characters sent right, wrong, left out or
+//! added, and how well each was timed.
+use crate::loopback::edit_distance;
+use crate::term::print_lines;
+use crate::timings;
+use rand::prelude::SliceRandom;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::time::Duration;
+use tabled::settings::style::Style;
+use tabled::{Table, Tabled};
+
+const WORDS: &[&str] = &[
+    "THE", "AND", "NAME", "RIG", "ANT", "POWER", "HERE", "GOOD", "FINE", "WX", "QTH", "RST",
+    "SUNNY", "CLOUDY", "RAIN", "TEMP", "RADIO", "MORSE", "KEY", "PADDLE", "HAM", "CALL", "TEST",
+    "BAND", "COPY", "AGN", "TNX", "FER", "QSO", "CUL", "BEST", "HOPE", "SOON", "WATTS", "DIPOLE",
+    "VERTICAL", "YAGI", "ANTENNA", "SIGNAL", "STRONG", "WEAK", "NOISE", "FADING", "QRM", "QRN",
+    "QSB", "QRS", "QRQ", "599", "73",
+];
+
+const SENTENCES: &[&str] = &[
+    "THE QUICK BROWN FOX JUMPS OVER THE LAZY DOG",
+    "TNX FER CALL UR RST 599",
+    "NAME HERE IS JOHN",
+    "RIG HERE IS 100 WATTS TO A DIPOLE",
+    "WX IS SUNNY ES WARM",
+    "HOPE TO CUL 73",
+    "QTH IS NEAR BOSTON",
+    "PSE QRS I AM NEW TO CW",
+    "GOOD COPY BUT QSB",
+    "MORSE CODE IS FUN",
+];
+
+const PREFIXES: &[&str] = &[
+    "K", "W", "N", "AA", "KD", "VE", "G", "M", "DL", "F", "I", "EA", "OH", "PA", "SP", "JA", "VK",
+    "ZL", "PY", "LU",
+];
+
+/// What the targets are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetKind {
+    Words,
+    Callsigns,
+    Sentences,
+    /// Any of the others
+    Mixed,
+}
+
+impl std::str::FromStr for TargetKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "words" => TargetKind::Words,
+            "callsigns" => TargetKind::Callsigns,
+            "sentences" => TargetKind::Sentences,
+            "mixed" => TargetKind::Mixed,
+            _ => anyhow::bail!(
+                "unknown kind of targets `{s}` (expected words, callsigns, sentences or mixed)"
+            ),
+        })
+    }
+}
+
+/// A random callsign: a prefix, a digit and a suffix of 1 to 3 letters.
+fn random_callsign(rng: &mut impl Rng) -> String {
+    let prefix = PREFIXES.choose(rng).expect("PREFIXES is not empty");
+    let suffix: String = (0..rng.gen_range(1..=3))
+        .map(|_| rng.gen_range(b'A'..=b'Z') as char)
+        .collect();
+    format!("{prefix}{}{suffix}", rng.gen_range(0..10))
+}
+
+/// `count` random targets of a kind
+pub fn targets(kind: TargetKind, count: u32) -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..count)
+        .map(|_| {
+            let kind = match kind {
+                TargetKind::Mixed => *[
+                    TargetKind::Words,
+                    TargetKind::Callsigns,
+                    TargetKind::Sentences,
+                ]
+                .choose(&mut rng)
+                .expect("not empty"),
+                kind => kind,
+            };
+            match kind {
+                TargetKind::Words | TargetKind::Mixed => WORDS
+                    .choose(&mut rng)
+                    .expect("WORDS is not empty")
+                    .to_string(),
+                TargetKind::Callsigns => random_callsign(&mut rng),
+                TargetKind::Sentences => SENTENCES
+                    .choose(&mut rng)
+                    .expect("SENTENCES is not empty")
+                    .to_string(),
+            }
+        })
+        .collect()
+}
+
+/// Aligns the characters of `sent` with those of `target`, with as
+/// few edits as possible (see `edit_distance`): pairs of the target's
+/// character (None when added) and the one sent (None when left out).
+pub fn align(target: &str, sent: &str) -> Vec<(Option<char>, Option<char>)> {
+    let a: Vec<char> = target.chars().collect();
+    let b: Vec<char> = sent.chars().collect();
+    // The distances between all the prefixes:
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = distances[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            distances[i][j] = substitution
+                .min(distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1);
+        }
+    }
+    // Back from the end, preferring matches and substitutions:
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > 0 || j > 0 {
+        let diagonal = i > 0
+            && j > 0
+            && distances[i][j] == distances[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+        if diagonal {
+            pairs.push((Some(a[i - 1]), Some(b[j - 1])));
+            (i, j) = (i - 1, j - 1);
+        } else if i > 0 && distances[i][j] == distances[i - 1][j] + 1 {
+            pairs.push((Some(a[i - 1]), None));
+            i -= 1;
+        } else {
+            pairs.push((None, Some(b[j - 1])));
+            j -= 1;
+        }
+    }
+    pairs.reverse();
+    pairs
+}
+
+/// A character of the target, and how it was sent
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradedCharacter {
+    /// None when it was added
+    pub target: Option<char>,
+    /// None when it was left out
+    pub sent: Option<char>,
+    /// How well it was timed [0.0..1.0] (see `timings::decode`)
+    pub timing: Option<f32>,
+}
+
+/// What was keyed for a target
+#[derive(Debug, Clone)]
+pub struct Grade {
+    pub characters: Vec<GradedCharacter>,
+    pub accuracy: f64, // [0.0..1.0]
+}
+
+impl Grade {
+    /// The mean timing of the characters sent
+    pub fn timing(&self) -> Option<f64> {
+        mean(self.characters.iter().filter_map(|c| c.timing))
+    }
+}
+
+fn mean(values: impl Iterator<Item = f32>) -> Option<f64> {
+    let (sum, count) = values.fold((0., 0), |(sum, count), v| (sum + v as f64, count + 1));
+    (count > 0).then(|| sum / count as f64)
+}
+
+/// Grades the runs of the key (down or up, for how long) against the
+/// target, decoding them with `dot_duration`.
+pub fn grade(target: &str, runs: &[(bool, Duration)], dot_duration: u32) -> Grade {
+    let target = target.trim().to_uppercase();
+    let decoded = timings::decode(runs, dot_duration);
+    let distance = edit_distance(&target, &decoded.text);
+    let accuracy = 1.0 - (distance as f64 / target.chars().count().max(1) as f64).min(1.0);
+    // The decoded characters (with their timing) are those sent, but
+    // the spaces:
+    let mut timings = decoded.characters.iter().map(|c| c.confidence);
+    let characters = align(&target, &decoded.text)
+        .into_iter()
+        .map(|(target, sent)| GradedCharacter {
+            target,
+            sent,
+            timing: sent
+                .filter(|c| !c.is_whitespace())
+                .and_then(|_| timings.next()),
+        })
+        .collect();
+    Grade {
+        characters,
+        accuracy,
+    }
+}
+
+/// The target and what was sent, aligned, with a mark under each
+/// mistake: `^` sent wrong, `-` left out, `+` added.
+fn print_grade(grade: &Grade, text: &mut String) -> fmt::Result {
+    let (mut target, mut sent, mut marks) = (String::new(), String::new(), String::new());
+    for character in &grade.characters {
+        target.push(character.target.unwrap_or('_'));
+        sent.push(character.sent.unwrap_or('_'));
+        marks.push(match (character.target, character.sent) {
+            (Some(t), Some(s)) if t == s => ' ',
+            (Some(_), Some(_)) => '^',
+            (Some(_), None) => '-',
+            (None, _) => '+',
+        });
+    }
+    writeln!(text, "\nTarget: {target}")?;
+    writeln!(text, "Sent:   {sent}")?;
+    if !marks.trim().is_empty() {
+        writeln!(text, "        {}", marks.trim_end())?;
+    }
+    let timing = grade
+        .timing()
+        .map_or("-".to_string(), |t| format!("{:.0}%", t * 100.));
+    writeln!(
+        text,
+        "Accuracy: {:.0}%  Timing: {timing}",
+        grade.accuracy * 100.
+    )
+}
+
+#[derive(Tabled)]
+struct SummaryRow {
+    character: char,
+    count: u32,
+    correct: u32,
+    wrong: u32,
+    missed: u32,
+    timing: String,
+}
+
+/// The results of all the targets: a table by character (worst first),
+/// the overall accuracy and timing, and a grade.
+fn print_summary(grades: &[Grade], text: &mut String) -> fmt::Result {
+    // Count, correct, wrong, missed, timings by character of the targets:
+    let mut stats: BTreeMap<char, (u32, u32, u32, u32, Vec<f32>)> = BTreeMap::new();
+    for character in grades.iter().flat_map(|grade| &grade.characters) {
+        let Some(target) = character.target.filter(|c| !c.is_whitespace()) else {
+            continue;
+        };
+        let entry = stats.entry(target).or_default();
+        entry.0 += 1;
+        match character.sent {
+            Some(sent) if sent == target => entry.1 += 1,
+            Some(_) => entry.2 += 1,
+            None => entry.3 += 1,
+        }
+        entry.4.extend(character.timing);
+    }
+    let mut summary: Vec<(f64, SummaryRow)> = stats
+        .into_iter()
+        .map(|(character, (count, correct, wrong, missed, timings))| {
+            let timing = mean(timings.into_iter());
+            let row = SummaryRow {
+                character,
+                count,
+                correct,
+                wrong,
+                missed,
+                timing: timing.map_or("-".to_string(), |t| format!("{:.0}%", t * 100.)),
+            };
+            (timing.unwrap_or_default(), row)
+        })
+        .collect();
+    // Sort primarily by mistakes, then by timing:
+    summary.sort_by(|(timing_a, a), (timing_b, b)| {
+        (b.wrong + b.missed, timing_a)
+            .partial_cmp(&(a.wrong + a.missed, timing_b))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    let mut table = Table::new(summary.into_iter().map(|(_, row)| row));
+    let table = table.with(Style::rounded());
+    writeln!(
+        text,
+        "\nPractice complete!\n\nCharacter Performance Summary:\n\n{table}"
+    )?;
+
+    let accuracy = grades.iter().map(|g| g.accuracy).sum::<f64>() / grades.len().max(1) as f64;
+    let timing = mean(
+        grades
+            .iter()
+            .flat_map(|grade| &grade.characters)
+            .filter_map(|c| c.timing),
+    )
+    .unwrap_or_default();
+    let perfect = grades.iter().filter(|g| g.accuracy == 1.).count();
+    writeln!(text, "\nResults:")?;
+    writeln!(text, "You sent {perfect}/{} perfectly!", grades.len())?;
+    writeln!(text, "Accuracy: {:.0}%", accuracy * 100.)?;
+    writeln!(text, "Timing: {:.0}%", timing * 100.)?;
+
+    let grade = match (accuracy * 100., timing >= 0.75) {
+        (95.0..=100.0, true) => "A+",
+        (90.0..=100.0, _) => "A",
+        (80.0..90.0, _) => "B",
+        (70.0..80.0, _) => "C",
+        (60.0..70.0, _) => "D",
+        _ => "F",
+    };
+    writeln!(text, "\nYour grade: {grade}")?;
+    let comment = match grade {
+        "A+" => "Phenomenal! Clean copy, and a fist anyone can copy.",
+        "A" => "Excellent work! A little cleaner and you'll be perfect.",
+        "B" => "Great job! Keep honing your sending.",
+        "C" => "Good effort! Slow down and focus on each character.",
+        "D" => "Keep at it! Try a slower speed with --wpm.",
+        _ => "Don't give up! Consistency and practice will help.",
+    };
+    writeln!(text, "{comment}")
+}
+
+/// Ends the practice after the last target (through the input's loop,
+/// see `is_finished`)
+#[derive(Debug)]
+pub struct Finished;
+
+impl fmt::Display for Finished {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the sending practice is finished")
+    }
+}
+
+impl std::error::Error for Finished {}
+
+/// Whether the input stopped because the practice is finished
+pub fn is_finished(error: &anyhow::Error) -> bool {
+    error.chain().any(|e| {
+        e.downcast_ref::<std::io::Error>()
+            .and_then(|e| e.get_ref())
+            .is_some_and(|e| e.is::<Finished>())
+    })
+}
+
+/// Shows the targets one by one, and grades what is keyed for each.
+pub struct SendTrainer {
+    dot_duration: u32,
+    targets: Vec<String>,
+    grades: Vec<Grade>,
+    runs: Vec<(bool, Duration)>,
+    prompted: bool,
+}
+
+impl SendTrainer {
+    pub fn new(targets: Vec<String>, dot_duration: u32) -> Self {
+        Self {
+            dot_duration,
+            targets,
+            grades: Vec::new(),
+            runs: Vec::new(),
+            prompted: false,
+        }
+    }
+
+    /// Shows the next target, once.
+    pub fn prompt(&mut self) {
+        if self.prompted {
+            return;
+        }
+        if let Some(target) = self.targets.get(self.grades.len()) {
+            print_lines(&format!(
+                "\n[{}/{}] Key: {target}\n",
+                self.grades.len() + 1,
+                self.targets.len()
+            ));
+        }
+        self.prompted = true;
+    }
+
+    /// The key was down (or up) for `duration`.
+    pub fn signal(&mut self, duration: Duration, was_down: bool) {
+        self.runs.push((was_down, duration));
+    }
+
+    /// Grades what was keyed for the target, then shows the next one,
+    /// or the results and `Finished` after the last.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        let runs = std::mem::take(&mut self.runs);
+        let Some(target) = self.targets.get(self.grades.len()) else {
+            return Ok(());
+        };
+        if !runs.iter().any(|(down, _)| *down) {
+            return Ok(());
+        }
+        let grade = grade(target, &runs, self.dot_duration);
+        let mut text = String::new();
+        let _ = print_grade(&grade, &mut text);
+        self.grades.push(grade);
+        self.prompted = false;
+        if self.grades.len() < self.targets.len() {
+            print_lines(&text);
+            self.prompt();
+            return Ok(());
+        }
+        let _ = print_summary(&self.grades, &mut text);
+        print_lines(&text);
+        Err(std::io::Error::other(Finished))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timings::keyed;
+
+    #[test]
+    fn test_align() {
+        assert_eq!(
+            align("K1ABC", "K1ADC"),
+            [
+                (Some('K'), Some('K')),
+                (Some('1'), Some('1')),
+                (Some('A'), Some('A')),
+                (Some('B'), Some('D')),
+                (Some('C'), Some('C'))
+            ]
+        );
+        assert_eq!(
+            align("CQ", "CQQ"),
+            [
+                (Some('C'), Some('C')),
+                (None, Some('Q')),
+                (Some('Q'), Some('Q'))
+            ]
+        );
+        assert_eq!(align("TEST", "TST")[1], (Some('E'), None));
+    }
+
+    #[test]
+    fn test_grade() {
+        // TNX keyed as TNK, in perfect timing:
+        let grade = grade("tnx", &keyed("TNK", 60), 60);
+        assert!((grade.accuracy - 2. / 3.).abs() < 1e-9);
+        assert_eq!(grade.characters[2].target, Some('X'));
+        assert_eq!(grade.characters[2].sent, Some('K'));
+        assert_eq!(grade.timing(), Some(1.));
+    }
+
+    #[test]
+    fn test_grade_sentence() {
+        let sentence = "THE QUICK BROWN FOX JUMPS OVER THE LAZY DOG";
+        for dot_duration in [30, 60, 100] {
+            let grade = grade(sentence, &keyed(sentence, dot_duration), dot_duration);
+            assert_eq!(grade.accuracy, 1., "at {dot_duration}ms dots");
+            assert_eq!(grade.timing(), Some(1.));
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use crate::key::Key;
+use crate::prelude::*;
+use anyhow::Context;
+use serialport::SerialPort;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A serial port that may be shared by several users (e.g. PTT on RTS
+/// and CW keying on DTR of the same cable)
+pub type SharedPort = Arc<Mutex<Box<dyn SerialPort>>>;
+
+/// Ports are opened exclusively, so every user of a port shares one handle.
+static PORTS: Mutex<BTreeMap<String, SharedPort>> = Mutex::new(BTreeMap::new());
+
+/// Opens the serial port (or returns the handle already opened for it).
+pub fn open_port(port_name: &str) -> anyhow::Result<SharedPort> {
+    let mut ports = PORTS.lock().unwrap();
+    if let Some(port) = ports.get(port_name) {
+        return Ok(port.clone());
+    }
+    let port = serialport::new(port_name, 9_600)
+        .timeout(Duration::from_millis(100))
+        .open()
+        .with_context(|| format!("opening serial port `{}`", port_name))?;
+    let port = Arc::new(Mutex::new(port));
+    ports.insert(port_name.to_string(), port.clone());
+    Ok(port)
+}
+
+/// A modem control line of a serial port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialLine {
+    Rts,
+    Dtr,
+}
+
+impl FromStr for SerialLine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rts" => Ok(SerialLine::Rts),
+            "dtr" => Ok(SerialLine::Dtr),
+            _ => Err(format!(
+                "Invalid serial line `{s}` (expected `rts` or `dtr`)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SerialLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SerialLine::Rts => write!(f, "RTS"),
+            SerialLine::Dtr => write!(f, "DTR"),
+        }
+    }
+}
+
+/// Keys one line of a serial port
+pub struct SerialLineKey {
+    port: SharedPort,
+    line: SerialLine,
+}
+
+impl SerialLineKey {
+    /// Opens the port and de-asserts the line, since opening a port
+    /// may raise the modem control lines.
+    pub fn open(port_name: &str, line: SerialLine) -> anyhow::Result<Self> {
+        let mut key = Self {
+            port: open_port(port_name)?,
+            line,
+        };
+        key.set(false)?;
+        Ok(key)
+    }
+}
+
+impl Key for SerialLineKey {
+    fn set(&mut self, down: bool) -> anyhow::Result<()> {
+        let mut port = self.port.lock().unwrap();
+        match self.line {
+            SerialLine::Rts => port.write_request_to_send(down),
+            SerialLine::Dtr => port.write_data_terminal_ready(down),
+        }
+        .with_context(|| format!("setting {} {}", self.line, if down { "on" } else { "off" }))?;
+        trace!("{} {}", self.line, if down { "ON" } else { "OFF" });
+        Ok(())
+    }
+}
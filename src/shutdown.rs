@@ -0,0 +1,188 @@
+//! Makes sure every transmitter output is left unkeyed, whatever way
+//! the program ends (Ctrl-C, SIGTERM, a panic or an early exit), and
+//! that no key stays down longer than `--max-key-down`.
+use crate::key::Key;
+use crate::prelude::*;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Once, Weak};
+use std::time::{Duration, Instant};
+
+/// How often the watchdog checks the keys
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(50);
+
+static MAX_KEY_DOWN: AtomicU64 = AtomicU64::new(u64::MAX); // Set by `init`
+static KEYS: Mutex<Vec<Weak<Mutex<GuardState>>>> = Mutex::new(Vec::new());
+static WATCHDOG: Once = Once::new();
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+struct GuardState {
+    name: String,
+    key: Box<dyn Key>,
+    down_since: Option<Instant>,
+    watchdog: bool,
+}
+
+impl GuardState {
+    fn unkey(&mut self) {
+        if let Err(e) = self.key.set(false) {
+            error!("Failed to unkey {}: {e:#}", self.name);
+        }
+        self.down_since = None;
+    }
+}
+
+/// A key that is unkeyed on shutdown, and (optionally) force-unkeyed by
+/// the watchdog when it stays down too long. Unkeys when dropped.
+pub struct GuardedKey {
+    state: Arc<Mutex<GuardState>>,
+}
+
+impl Key for GuardedKey {
+    fn set(&mut self, down: bool) -> anyhow::Result<()> {
+        if down && SHUTTING_DOWN.load(Ordering::SeqCst) {
+            anyhow::bail!("shutting down");
+        }
+        let mut state = self.state.lock().unwrap();
+        state.key.set(down)?;
+        state.down_since = match (down, state.down_since) {
+            (true, Some(since)) => Some(since),
+            (true, None) => Some(Instant::now()),
+            (false, _) => None,
+        };
+        Ok(())
+    }
+}
+
+impl Drop for GuardedKey {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().unkey();
+    }
+}
+
+/// Registers `key` to be unkeyed on shutdown. With `watchdog`, the key
+/// is also force-unkeyed if it stays down longer than the maximum key
+/// down time (meant for CW element keying, not for PTT which may stay
+/// keyed for a whole message).
+pub fn guard(name: &str, key: impl Key + 'static, watchdog: bool) -> GuardedKey {
+    let state = Arc::new(Mutex::new(GuardState {
+        name: name.to_string(),
+        key: Box::new(key),
+        down_since: None,
+        watchdog,
+    }));
+    let mut keys = KEYS.lock().unwrap();
+    keys.retain(|key| key.strong_count() > 0);
+    keys.push(Arc::downgrade(&state));
+    drop(keys);
+    if watchdog {
+        WATCHDOG.call_once(|| {
+            std::thread::spawn(watchdog_loop);
+        });
+    }
+    GuardedKey { state }
+}
+
+fn live_keys() -> Vec<Arc<Mutex<GuardState>>> {
+    // Don't block forever if a thread panicked while holding the lock:
+    let keys = KEYS.lock().unwrap_or_else(|e| e.into_inner());
+    keys.iter().filter_map(Weak::upgrade).collect()
+}
+
+fn watchdog_loop() {
+    loop {
+        std::thread::sleep(WATCHDOG_INTERVAL);
+        release_stuck_keys(Duration::from_millis(MAX_KEY_DOWN.load(Ordering::Relaxed)));
+    }
+}
+
+/// Forces up every watched key that has been down longer than
+/// `max_key_down`.
+fn release_stuck_keys(max_key_down: Duration) {
+    for key in live_keys() {
+        let mut state = key.lock().unwrap_or_else(|e| e.into_inner());
+        let stuck = state
+            .down_since
+            .is_some_and(|since| since.elapsed() > max_key_down);
+        if state.watchdog && stuck {
+            error!(
+                "{} was keyed down for more than {}ms, forcing it up!",
+                state.name,
+                max_key_down.as_millis()
+            );
+            state.unkey();
+        }
+    }
+}
+
+/// Unkeys every registered key.
+pub fn unkey_all() {
+    for key in live_keys() {
+        key.lock().unwrap_or_else(|e| e.into_inner()).unkey();
+    }
+}
+
+/// Installs the SIGINT/SIGTERM handler and the panic hook that unkey
+/// every output, and sets the maximum key down time enforced by the
+/// watchdog.
+pub fn init(max_key_down: Duration) {
+    MAX_KEY_DOWN.store(max_key_down.as_millis() as u64, Ordering::Relaxed);
+    if let Err(e) = ctrlc::set_handler(|| {
+        warn!("Interrupted, unkeying all outputs.");
+        exit(130);
+    }) {
+        warn!("Could not install the Ctrl-C handler: {e}");
+    }
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        unkey_all();
+        default_hook(info);
+    }));
+}
+
+/// Unkeys every output and exits the process (use this instead of
+/// `std::process::exit`, which skips destructors).
+pub fn exit(code: i32) -> ! {
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+    unkey_all();
+    std::process::exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct SharedKey(Arc<Mutex<Vec<bool>>>);
+
+    impl Key for SharedKey {
+        fn set(&mut self, down: bool) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(down);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_watchdog_and_unkey_all() {
+        let cw = SharedKey::default();
+        let ptt = SharedKey::default();
+        let mut cw_key = guard("test CW key", cw.clone(), true);
+        let mut ptt_key = guard("test PTT", ptt.clone(), false);
+        cw_key.set(true).unwrap();
+        ptt_key.set(true).unwrap();
+
+        // Nothing is stuck yet:
+        release_stuck_keys(Duration::from_millis(100));
+        assert_eq!(*cw.0.lock().unwrap(), vec![true]);
+
+        // The watchdog forces the stuck CW key up, but leaves PTT alone:
+        std::thread::sleep(Duration::from_millis(150));
+        release_stuck_keys(Duration::from_millis(100));
+        assert_eq!(*cw.0.lock().unwrap(), vec![true, false]);
+        assert_eq!(*ptt.0.lock().unwrap(), vec![true]);
+
+        unkey_all();
+        assert_eq!(*ptt.0.lock().unwrap(), vec![true, false]);
+        drop(cw_key);
+        assert_eq!(cw.0.lock().unwrap().last(), Some(&false));
+    }
+}
@@ -5,7 +5,7 @@ use crossterm::{
     execute,
     terminal::{Clear, ClearType},
 };
-use std::io::stdout;
+use std::io::{stdout, Write};
 
 #[allow(dead_code)]
 pub fn clear_screen() {
@@ -40,11 +40,15 @@ pub fn log_message(message: &Message) {
         wrapped_lines.push(current_line);
     }
 
-    // Print the first line with the timestamp aligned to the right
+    // Print the first line with the timestamp (and rig frequency) aligned to the right
+    let timestamp = match &message.rig {
+        Some(rig) => format!("{rig}  {}", message.timestamp),
+        None => message.timestamp.clone(),
+    };
     if let Some(first_line) = wrapped_lines.first() {
-        let padding = terminal_width.saturating_sub(first_line.len() + message.timestamp.len());
+        let padding = terminal_width.saturating_sub(first_line.len() + timestamp.len());
         let spaces = " ".repeat(padding);
-        println!("{}{}{}", first_line, spaces, message.timestamp);
+        println!("{}{}{}", first_line, spaces, timestamp);
     }
 
     // Print the rest of the wrapped lines
@@ -55,3 +59,14 @@ pub fn log_message(message: &Message) {
     // Print an empty line at the end
     println!();
 }
+
+/// Prints text of several lines, with carriage returns in raw mode
+/// (`practice-key`).
+pub fn print_lines(text: &str) {
+    let raw = crossterm::terminal::is_raw_mode_enabled().unwrap_or(false);
+    match raw {
+        true => print!("{}", text.replace('\n', "\r\n")),
+        false => print!("{text}"),
+    }
+    let _ = stdout().flush();
+}
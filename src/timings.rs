@@ -0,0 +1,312 @@
+//! Offline decoding of key timings, for tuning the decoder without
+//! hardware: mark/space durations from a CSV file, or the edges of a
+//! `.cwlog` recording, go through the same decoder as live keying and
+//! audio, and every character gets a confidence from how close its
+//! elements are to the ideal timing.
+//!
+//! The CSV has one run of the key per line, `mark` (or `down`, `1`) or
+//! `space` (`up`, `0`) and its duration in milliseconds. Lines starting
+//! with `#` are comments, except `# dot_ms: 60` giving the speed.
+use crate::cwlog;
+use crate::morse::{get_decoder, text_to_morse};
+use anyhow::{anyhow, bail, Context};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::time::Duration;
+
+/// The key's runs (down or up, for how long), and the speed if known
+#[derive(Debug, Default)]
+pub struct Timings {
+    pub runs: Vec<(bool, Duration)>,
+    pub dot_ms: Option<u32>,
+}
+
+/// Parses mark/space durations.
+pub fn parse_csv(reader: impl BufRead) -> anyhow::Result<Timings> {
+    let mut timings = Timings::default();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix('#') {
+            if let Some(dot_ms) = comment.trim().strip_prefix("dot_ms:") {
+                let dot_ms = dot_ms.trim().parse().context("invalid dot_ms")?;
+                timings.dot_ms = Some(dot_ms);
+            }
+            continue;
+        }
+        let (state, ms) = match line.split_once(',') {
+            Some((state, ms)) => (state.trim().to_ascii_lowercase(), ms.trim()),
+            None if line.is_empty() => continue,
+            None => bail!("line {}: expected `mark,<ms>` or `space,<ms>`", i + 1),
+        };
+        let mark = match state.as_str() {
+            "mark" | "down" | "1" => true,
+            "space" | "up" | "0" => false,
+            // A header line:
+            _ if timings.runs.is_empty() && ms.parse::<f64>().is_err() => continue,
+            _ => bail!("line {}: unknown state `{state}`", i + 1),
+        };
+        let ms: f64 = ms
+            .parse()
+            .map_err(|_| anyhow!("line {}: invalid duration `{ms}`", i + 1))?;
+        timings
+            .runs
+            .push((mark, Duration::from_secs_f64(ms.max(0.) / 1000.)));
+    }
+    Ok(timings)
+}
+
+/// The runs between the edges of a recording
+fn edge_runs(edges: &[cwlog::Edge]) -> Vec<(bool, Duration)> {
+    edges
+        .windows(2)
+        .map(|pair| (pair[0].down, pair[1].at().saturating_sub(pair[0].at())))
+        .collect()
+}
+
+/// Reads timings from a CSV file or a `.cwlog` recording.
+pub fn read(path: &str) -> anyhow::Result<Timings> {
+    let file = File::open(path).with_context(|| format!("opening {path}"))?;
+    let mut reader = BufReader::new(file);
+    // Recordings are JSON lines:
+    let recording = reader.fill_buf()?.trim_ascii_start().starts_with(b"{");
+    match recording {
+        true => {
+            let (header, edges) =
+                cwlog::parse(reader).with_context(|| format!("reading recording {path}"))?;
+            Ok(Timings {
+                runs: edge_runs(&edges),
+                dot_ms: Some(header.dot_ms),
+            })
+        }
+        false => parse_csv(reader).with_context(|| format!("reading timings {path}")),
+    }
+}
+
+/// A decoded character and how sure the decoder can be of it [0.0..1.0]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedCharacter {
+    pub character: char,
+    pub confidence: f32,
+}
+
+#[derive(Debug, Default)]
+pub struct Decoded {
+    pub text: String,
+    pub characters: Vec<DecodedCharacter>,
+}
+
+/// How close a duration is to the expected one: 1.0 when equal, down
+/// to 0.0 halfway (on a log scale) to 3 times or a third of it, where
+/// a dot can't be told from a dash, or an element gap from a
+/// character gap.
+fn closeness(duration: Duration, expected: Duration) -> f32 {
+    let ratio = duration.as_secs_f32() / expected.as_secs_f32();
+    (1. - ratio.ln().abs() / (3_f32.ln() / 2.)).clamp(0., 1.)
+}
+
+/// The confidence in a character keyed with `runs` (its marks and the
+/// gaps between them): the closeness of its worst timed element.
+fn confidence(character: char, runs: &[(bool, Duration)], dot: Duration) -> f32 {
+    let pattern: Vec<char> = text_to_morse(&character.to_string())
+        .chars()
+        .filter(|c| matches!(c, '.' | '-'))
+        .collect();
+    let marks = runs.iter().filter(|(mark, _)| *mark).count();
+    if pattern.is_empty() || marks != pattern.len() {
+        return 0.; // Not decoded (?) or not from these runs
+    }
+    let mut elements = pattern.iter();
+    runs.iter()
+        .map(|&(mark, duration)| {
+            let expected = match mark.then(|| elements.next()).flatten() {
+                Some('-') => dot * 3,
+                _ => dot,
+            };
+            closeness(duration, expected)
+        })
+        .fold(1., f32::min)
+}
+
+impl Decoded {
+    /// Takes the characters the decoder added to its message, keyed
+    /// with `runs` (since the previous ones). Returns false if none.
+    fn take_characters(
+        &mut self,
+        message: &str,
+        runs: &mut Vec<(bool, Duration)>,
+        dot: Duration,
+    ) -> bool {
+        let added = message.get(self.text.len()..).unwrap_or_default();
+        if added.trim().is_empty() {
+            return false;
+        }
+        for character in added.chars().filter(|c| !c.is_whitespace()) {
+            self.characters.push(DecodedCharacter {
+                character,
+                confidence: confidence(character, runs, dot),
+            });
+        }
+        self.text = message.to_string();
+        runs.clear();
+        true
+    }
+}
+
+/// Decodes the runs of a key with the decoder used for live keying and
+/// audio, expecting `dot_duration`.
+pub fn decode(runs: &[(bool, Duration)], dot_duration: u32) -> Decoded {
+    let dot = Duration::from_millis(dot_duration.into());
+    let mut decoder = get_decoder(dot_duration);
+    let mut decoded = Decoded::default();
+    let mut character_runs = Vec::new();
+    for &(mark, duration) in runs.iter().skip_while(|(mark, _)| !mark) {
+        let ms = duration.as_millis().min(u16::MAX.into()) as u16;
+        decoder.signal_event(ms, mark);
+        // A long enough space ends the character:
+        let ended = !mark && decoded.take_characters(decoder.message(), &mut character_runs, dot);
+        if !ended {
+            character_runs.push((mark, duration));
+        }
+    }
+    decoder.signal_event_end(false);
+    // The trailing space isn't part of the last character:
+    if character_runs.last().is_some_and(|(mark, _)| !mark) {
+        character_runs.pop();
+    }
+    decoded.take_characters(decoder.message(), &mut character_runs, dot);
+    decoded.text = decoded.text.trim().to_string();
+    decoded
+}
+
+/// Prints the text, and the confidence of each character.
+pub fn print_decoded(decoded: &Decoded, output_morse: bool) {
+    match output_morse {
+        true => println!("{}", text_to_morse(&decoded.text)),
+        false => println!("{}", decoded.text),
+    }
+    println!();
+    for character in &decoded.characters {
+        println!(
+            "{}  {:<8} {:>3.0}%",
+            character.character,
+            text_to_morse(&character.character.to_string()).trim(),
+            character.confidence * 100.
+        );
+    }
+    if !decoded.characters.is_empty() {
+        let mean = decoded.characters.iter().map(|c| c.confidence).sum::<f32>()
+            / decoded.characters.len() as f32;
+        println!("\nMean confidence: {:.0}%", mean * 100.);
+    }
+}
+
+/// The runs of text keyed with the standard timing at `dot_duration`:
+/// gaps of 1 dot between elements, 3 between characters and 7 between
+/// words.
+#[cfg(test)]
+pub(crate) fn keyed(text: &str, dot_duration: u32) -> Vec<(bool, Duration)> {
+    let dots = |dots: u32| Duration::from_millis((dots * dot_duration).into());
+    let mut runs = Vec::new();
+    for word in text.split_whitespace() {
+        for character in word.chars() {
+            for element in text_to_morse(&character.to_string()).chars() {
+                match element {
+                    '.' => runs.push((true, dots(1))),
+                    '-' => runs.push((true, dots(3))),
+                    _ => continue,
+                }
+                runs.push((false, dots(1)));
+            }
+            runs.pop();
+            runs.push((false, dots(3)));
+        }
+        runs.pop();
+        runs.push((false, dots(7)));
+    }
+    runs.pop();
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loopback::edit_distance;
+
+    #[test]
+    fn test_decode_confidence() {
+        let decoded = decode(&keyed("PARIS", 60), 60);
+        assert_eq!(decoded.text, "PARIS");
+        assert_eq!(decoded.characters.len(), 5);
+        assert!(decoded.characters.iter().all(|c| c.confidence == 1.));
+
+        // A dot stretched to almost twice its length is barely told from a dash:
+        let mut runs = keyed("E", 60);
+        runs[0].1 = runs[0].1 * 9 / 5;
+        let decoded = decode(&runs, 60);
+        assert_eq!(decoded.text, "E");
+        assert!(decoded.characters[0].confidence < 0.5);
+    }
+
+    #[test]
+    fn test_decode_qrq() {
+        for dot_duration in [15, 20, 30, 60, 120] {
+            let decoded = decode(&keyed("CQ SOS", dot_duration), dot_duration);
+            assert_eq!(decoded.text, "CQ SOS", "at {dot_duration} ms dots");
+        }
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let csv = "# dot_ms: 80\nstate,ms\nmark,80\nspace,80.5\n\ndown,240\n";
+        let timings = parse_csv(csv.as_bytes()).unwrap();
+        assert_eq!(timings.dot_ms, Some(80));
+        assert_eq!(
+            timings.runs,
+            [
+                (true, Duration::from_millis(80)),
+                (false, Duration::from_micros(80500)),
+                (true, Duration::from_millis(240))
+            ]
+        );
+        assert!(parse_csv("mark,x\n".as_bytes()).is_err());
+    }
+
+    /// The regression suite: timings in sample/timings, with the text
+    /// that was keyed in a `# text:` comment, and the accuracy the
+    /// decoder reaches at least in an `# accuracy:` comment (raise it
+    /// as the decoder gets better).
+    #[test]
+    fn test_sample_timings() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/sample/timings");
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+        for path in paths {
+            let path = path.to_str().unwrap();
+            let contents = std::fs::read_to_string(path).unwrap();
+            let comment = |name: &str| {
+                contents
+                    .lines()
+                    .find_map(|line| line.strip_prefix(&format!("# {name}:")))
+                    .map(str::trim)
+                    .unwrap_or_else(|| panic!("{path} has no `# {name}:` comment"))
+            };
+            let text = comment("text");
+            let minimum: f64 = comment("accuracy").trim_end_matches('%').parse().unwrap();
+            let timings = read(path).unwrap();
+            let decoded = decode(&timings.runs, timings.dot_ms.unwrap_or(60));
+            let distance = edit_distance(&decoded.text, text);
+            let accuracy = 100. * (1. - distance as f64 / text.chars().count() as f64);
+            println!("{path}: {accuracy:.1}% {:?}", decoded.text);
+            assert!(
+                accuracy >= minimum,
+                "{path}: {accuracy:.0}% accurate (at least {minimum}%), decoded {:?}",
+                decoded.text
+            );
+        }
+    }
+}
@@ -0,0 +1,428 @@
+use crate::key::Key;
+use crate::morse::{encode_morse, wpm_to_dot_length};
+use crate::prelude::*;
+use anyhow::{bail, Context};
+use serialport::SerialPort;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+/// WinKeyer serial speed (8 data bits, 2 stop bits, no parity)
+pub const BAUD_RATE: u32 = 1200;
+
+/// How long the keyer may take to answer a status request
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Host commands:
+pub const ADMIN: u8 = 0x00; // Followed by an admin command
+pub const SIDETONE: u8 = 0x01; // Followed by the sidetone divider
+pub const SPEED: u8 = 0x02; // Followed by the speed in WPM
+pub const CLEAR_BUFFER: u8 = 0x0A;
+pub const KEY_IMMEDIATE: u8 = 0x0B; // Followed by 1 (key down) or 0
+pub const SET_MODE: u8 = 0x0E; // Followed by the WK2 mode register
+pub const REQUEST_STATUS: u8 = 0x15;
+pub const PTT: u8 = 0x18; // Followed by 1 (on) or 0 (off)
+pub const BUFFERED_SPEED: u8 = 0x1C; // Followed by the speed in WPM
+pub const CANCEL_BUFFERED_SPEED: u8 = 0x1E;
+
+// Admin commands:
+pub const ADMIN_HOST_OPEN: u8 = 0x02;
+pub const ADMIN_HOST_CLOSE: u8 = 0x03;
+pub const ADMIN_ECHO: u8 = 0x04; // Followed by a byte to echo back
+
+// Status byte (sent by the keyer whenever its state changes):
+pub const STATUS_TAG: u8 = 0xC0; // Top three bits are 110
+pub const STATUS_MASK: u8 = 0xE0;
+pub const STATUS_XOFF: u8 = 0x01; // Buffer is more than 2/3 full
+pub const STATUS_BREAKIN: u8 = 0x02; // Paddle break-in
+pub const STATUS_BUSY: u8 = 0x04; // Keyer is sending
+pub const STATUS_WAIT: u8 = 0x10; // Keyer is waiting for an internal event
+
+// Mode register:
+pub const MODE_SERIAL_ECHO: u8 = 0x04; // Echo characters as they are sent
+
+// Speed pot byte (top two bits are 10):
+pub const SPEED_POT_TAG: u8 = 0x80;
+pub const SPEED_POT_MASK: u8 = 0xC0;
+
+/// Sidetone divider for the given frequency (the keyer plays 4000Hz / n)
+pub fn sidetone_divider(tone_freq: f32) -> u8 {
+    (4000.0 / tone_freq.max(1.0)).round().clamp(1.0, 10.0) as u8
+}
+
+/// The characters the keyer can send: uppercase letters, digits,
+/// punctuation and spaces.
+pub fn keyer_text(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_uppercase()
+        .chars()
+        .filter(|c| (' '..='Z').contains(c))
+        .collect()
+}
+
+/// A command sent by the host to the keyer
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostCommand {
+    HostOpen,
+    HostClose,
+    Echo(u8),
+    Sidetone(u8),
+    Speed(u8),
+    Clear,
+    KeyImmediate(bool),
+    Mode(u8),
+    RequestStatus,
+    Ptt(bool),
+    BufferedSpeed(u8),
+    CancelBufferedSpeed,
+    Text(u8),
+    Other(Vec<u8>), // Anything else, with its arguments
+}
+
+/// Number of argument bytes following a (non admin) command byte
+fn argument_count(command: u8) -> usize {
+    match command {
+        0x04 | 0x1B => 2,
+        0x05 => 3,
+        0x0F => 15,
+        0x07 | 0x08 | 0x0A | 0x13 | 0x15 | 0x1E | 0x1F => 0,
+        _ => 1,
+    }
+}
+
+/// Number of argument bytes following an admin command
+fn admin_argument_count(command: u8) -> usize {
+    match command {
+        0x00 | 0x04 | 0x0E | 0x0F | 0x16 | 0x19 => 1,
+        _ => 0,
+    }
+}
+
+/// Splits the byte stream from the host into commands
+#[derive(Default)]
+pub struct CommandParser {
+    pending: Vec<u8>,
+}
+
+impl CommandParser {
+    /// Adds a byte, returning the command it completes (if any).
+    pub fn push(&mut self, byte: u8) -> Option<HostCommand> {
+        self.pending.push(byte);
+        let expected = match self.pending[0] {
+            ADMIN if self.pending.len() < 2 => return None,
+            ADMIN => 2 + admin_argument_count(self.pending[1]),
+            b if b >= 0x20 => 1,
+            b => 1 + argument_count(b),
+        };
+        if self.pending.len() < expected {
+            return None;
+        }
+        let command = std::mem::take(&mut self.pending);
+        Some(match command[..] {
+            [ADMIN, ADMIN_HOST_OPEN] => HostCommand::HostOpen,
+            [ADMIN, ADMIN_HOST_CLOSE] => HostCommand::HostClose,
+            [ADMIN, ADMIN_ECHO, byte] => HostCommand::Echo(byte),
+            [SIDETONE, divider] => HostCommand::Sidetone(divider),
+            [SPEED, wpm] => HostCommand::Speed(wpm),
+            [CLEAR_BUFFER] => HostCommand::Clear,
+            [KEY_IMMEDIATE, down] => HostCommand::KeyImmediate(down != 0),
+            [SET_MODE, mode] => HostCommand::Mode(mode),
+            [REQUEST_STATUS] => HostCommand::RequestStatus,
+            [PTT, on] => HostCommand::Ptt(on != 0),
+            [BUFFERED_SPEED, wpm] => HostCommand::BufferedSpeed(wpm),
+            [CANCEL_BUFFERED_SPEED] => HostCommand::CancelBufferedSpeed,
+            [byte] if byte >= 0x20 => HostCommand::Text(byte),
+            _ => HostCommand::Other(command),
+        })
+    }
+}
+
+/// Drives a K1EL WinKeyer (WK2/WK3) in host mode
+pub struct WinKeyer {
+    port: Box<dyn SerialPort>,
+    status: u8,
+    wpm: u32, // To know how long sending may take
+}
+
+impl WinKeyer {
+    /// Opens the serial port and the host mode session.
+    pub fn open(port_name: &str) -> anyhow::Result<Self> {
+        let port = serialport::new(port_name, BAUD_RATE)
+            .stop_bits(serialport::StopBits::Two)
+            .timeout(Duration::from_millis(50))
+            .open()
+            .with_context(|| format!("opening WinKeyer port `{port_name}`"))?;
+        Self::host_open(port)
+    }
+
+    /// Opens the host mode session on an already opened port.
+    pub fn host_open(mut port: Box<dyn SerialPort>) -> anyhow::Result<Self> {
+        // Flush anything left over from a previous session:
+        let _ = port.clear(serialport::ClearBuffer::All);
+        port.write_all(&[ADMIN, ADMIN_HOST_OPEN])?;
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let mut version = [0];
+        loop {
+            match port.read(&mut version) {
+                Ok(1) => break,
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e).context("reading WinKeyer version"),
+            }
+            if Instant::now() > deadline {
+                bail!("WinKeyer did not answer the host open command");
+            }
+        }
+        info!("WinKeyer version {} host mode open", version[0]);
+        Ok(Self {
+            port,
+            status: STATUS_TAG,
+            wpm: 5, // The slowest speed, until it is set
+        })
+    }
+
+    pub fn set_speed(&mut self, wpm: u32) -> anyhow::Result<()> {
+        self.wpm = wpm.clamp(5, 99);
+        self.port.write_all(&[SPEED, self.wpm as u8])?;
+        Ok(())
+    }
+
+    pub fn set_sidetone(&mut self, tone_freq: f32) -> anyhow::Result<()> {
+        self.port
+            .write_all(&[SIDETONE, sidetone_divider(tone_freq)])?;
+        Ok(())
+    }
+
+    /// Returns a handle that aborts sending when keyed up (see `shutdown`).
+    pub fn abort_handle(&self) -> anyhow::Result<WinKeyerAbort> {
+        Ok(WinKeyerAbort {
+            port: self.port.try_clone()?,
+        })
+    }
+
+    /// Reads the bytes the keyer sent, keeping the latest status.
+    /// Returns whether a status byte was received.
+    fn poll(&mut self) -> anyhow::Result<bool> {
+        let mut buffer = [0; 64];
+        let length = match self.port.read(&mut buffer) {
+            Ok(length) => length,
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => 0,
+            Err(e) => return Err(e).context("reading from WinKeyer"),
+        };
+        let mut status = false;
+        for byte in &buffer[..length] {
+            match *byte {
+                b if b & STATUS_MASK == STATUS_TAG => {
+                    status = true;
+                    trace!("WinKeyer status {b:#04x}");
+                    if b & STATUS_BREAKIN != 0 && self.status & STATUS_BREAKIN == 0 {
+                        info!("WinKeyer paddle break-in");
+                    }
+                    self.status = b;
+                }
+                b if b & SPEED_POT_MASK == SPEED_POT_TAG => {
+                    trace!("WinKeyer speed pot {}", b & !SPEED_POT_MASK)
+                }
+                b => trace!("WinKeyer echo {:?}", b as char),
+            }
+        }
+        Ok(status)
+    }
+
+    /// How long the keyer takes to send `text` at the current speed
+    fn sending_time(&self, text: &str) -> Duration {
+        let tones = encode_morse(text, wpm_to_dot_length(self.wpm), 1.);
+        Duration::from_millis(tones.iter().map(|&(_, d)| d as u64).sum())
+    }
+
+    /// Sends text through the keyer, pausing while its buffer is full,
+    /// and waits until it has been sent.
+    pub fn send(&mut self, text: &str) -> anyhow::Result<()> {
+        let text = keyer_text(text);
+        let sending_time = self.sending_time(&text);
+        // However full the buffer, it empties within the whole text:
+        let deadline = Instant::now() + sending_time + ANSWER_TIMEOUT;
+        for byte in text.bytes() {
+            self.poll()?;
+            while self.status & STATUS_XOFF != 0 {
+                if Instant::now() > deadline {
+                    bail!("WinKeyer buffer stayed full");
+                }
+                self.poll()?;
+            }
+            self.port.write_all(&[byte])?;
+        }
+        self.wait(sending_time)
+    }
+
+    /// Waits until the keyer has finished sending, which should take
+    /// at most `sending_time` (plus some slack).
+    fn wait(&mut self, sending_time: Duration) -> anyhow::Result<()> {
+        // The busy status may take a moment to arrive:
+        let started = Instant::now();
+        let deadline = started + sending_time + 2 * ANSWER_TIMEOUT;
+        let mut answered = None;
+        loop {
+            self.port.write_all(&[REQUEST_STATUS])?;
+            if self.poll()? {
+                answered = Some(Instant::now());
+            } else if answered.unwrap_or(started).elapsed() > ANSWER_TIMEOUT {
+                bail!("WinKeyer stopped answering status requests");
+            }
+            let busy = self.status & (STATUS_BUSY | STATUS_XOFF | STATUS_WAIT) != 0;
+            if answered.is_some() && !busy && started.elapsed() > Duration::from_millis(200) {
+                return Ok(());
+            }
+            if Instant::now() > deadline {
+                bail!("WinKeyer is still busy after sending the text");
+            }
+        }
+    }
+}
+
+impl Drop for WinKeyer {
+    fn drop(&mut self) {
+        let _ = self.port.write_all(&[ADMIN, ADMIN_HOST_CLOSE]);
+        let _ = self.port.flush();
+    }
+}
+
+/// Clears the keyer's buffer when set up, so that shutdown aborts
+/// whatever the keyer is still sending.
+pub struct WinKeyerAbort {
+    port: Box<dyn SerialPort>,
+}
+
+impl Key for WinKeyerAbort {
+    fn set(&mut self, down: bool) -> anyhow::Result<()> {
+        if !down {
+            self.port.write_all(&[CLEAR_BUFFER])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use serialport::TTYPort;
+    #[cfg(unix)]
+    use std::sync::{Arc, Mutex};
+
+    #[cfg(unix)]
+    /// A minimal keyer on the other end of a pseudo terminal: answers
+    /// host open and status requests (with `status`, if any), and
+    /// records what the host sends.
+    fn fake_keyer(mut keyer: TTYPort, status: Option<u8>) -> Arc<Mutex<Vec<u8>>> {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        std::thread::spawn(move || {
+            let mut admin = false;
+            let mut buffer = [0; 64];
+            loop {
+                let length = match keyer.read(&mut buffer) {
+                    Ok(0) => return,
+                    Ok(length) => length,
+                    Err(e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
+                    Err(_) => return,
+                };
+                for byte in &buffer[..length] {
+                    let reply = match (admin, *byte) {
+                        (true, ADMIN_HOST_OPEN) => Some(23),
+                        (false, REQUEST_STATUS) => status,
+                        _ => None,
+                    };
+                    admin = !admin && *byte == ADMIN;
+                    if *byte != REQUEST_STATUS {
+                        log.lock().unwrap().push(*byte);
+                    }
+                    if let Some(reply) = reply {
+                        keyer.write_all(&[reply]).unwrap();
+                    }
+                }
+            }
+        });
+        received
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_winkeyer_host_session() {
+        let (keyer, host) = TTYPort::pair().expect("creating a pseudo terminal");
+        let received = fake_keyer(keyer, Some(STATUS_TAG));
+        // Closing the last handle of the terminal would discard unread bytes:
+        let _open = host.try_clone_native().unwrap();
+        let mut winkeyer = WinKeyer::host_open(Box::new(host)).unwrap();
+        winkeyer.set_speed(25).unwrap();
+        winkeyer.set_sidetone(800.0).unwrap();
+        winkeyer.send("cq  test\n").unwrap();
+        winkeyer.abort_handle().unwrap().set(false).unwrap();
+        drop(winkeyer);
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(
+            *received.lock().unwrap(),
+            [
+                &[ADMIN, ADMIN_HOST_OPEN, SPEED, 25, SIDETONE, 5][..],
+                b"CQ TEST",
+                &[CLEAR_BUFFER, ADMIN, ADMIN_HOST_CLOSE],
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_winkeyer_gives_up() {
+        for (status, error) in [
+            (None, "stopped answering"),
+            (Some(STATUS_TAG | STATUS_XOFF), "buffer stayed full"),
+            (Some(STATUS_TAG | STATUS_BUSY), "still busy"),
+        ] {
+            let (keyer, host) = TTYPort::pair().expect("creating a pseudo terminal");
+            fake_keyer(keyer, status);
+            let _open = host.try_clone_native().unwrap();
+            let mut winkeyer = WinKeyer::host_open(Box::new(host)).unwrap();
+            winkeyer.set_speed(99).unwrap();
+            winkeyer.port.write_all(&[REQUEST_STATUS]).unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+            winkeyer.poll().unwrap();
+            let e = winkeyer.send("EE").expect_err(&format!("{status:?}"));
+            assert!(e.to_string().contains(error), "{status:?}: {e}");
+        }
+    }
+
+    #[test]
+    fn test_command_parser() {
+        let mut parser = CommandParser::default();
+        let commands: Vec<HostCommand> = [
+            &[ADMIN, ADMIN_HOST_OPEN, SPEED, 25, 0x04, 10, 20][..],
+            b"K",
+            &[ADMIN, ADMIN_ECHO, 0x55, PTT, 1, CLEAR_BUFFER],
+        ]
+        .concat()
+        .into_iter()
+        .filter_map(|byte| parser.push(byte))
+        .collect();
+        assert_eq!(
+            commands,
+            vec![
+                HostCommand::HostOpen,
+                HostCommand::Speed(25),
+                HostCommand::Other(vec![0x04, 10, 20]),
+                HostCommand::Text(b'K'),
+                HostCommand::Echo(0x55),
+                HostCommand::Ptt(true),
+                HostCommand::Clear,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keyer_text() {
+        assert_eq!(keyer_text("5nn tu\t73 \u{e9}"), "5NN TU 73 ");
+        assert_eq!(sidetone_divider(600.0), 7);
+        assert_eq!(sidetone_divider(100.0), 10);
+    }
+}
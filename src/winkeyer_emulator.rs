@@ -0,0 +1,255 @@
+//! Pretends to be a WinKeyer on a pseudo terminal, so that loggers
+//! (N1MM, fldigi, ...) can send CW through code-smore's outputs.
+use crate::morse::{wpm_to_dot_length, CwOutput, MorsePlayer};
+use crate::prelude::*;
+use crate::ptt::PttHold;
+use crate::winkeyer::*;
+use anyhow::Context;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{mpsc, Arc};
+
+/// Firmware version reported to the host (WK2 v23)
+const VERSION: u8 = 23;
+
+/// Buffer fill levels that set and clear XOFF (the real keyer has a
+/// 128 byte buffer and asserts XOFF when it is 2/3 full)
+const XOFF_LEVEL: usize = 85;
+const XON_LEVEL: usize = 40;
+
+/// Things the host can put in the keyer's buffer
+enum Buffered {
+    Character(u8),
+    Speed(u8),
+    CancelSpeed,
+}
+
+/// Reads the host's commands, answering the ones that need an
+/// immediate reply and forwarding the rest to the sender.
+#[cfg(unix)]
+fn read_commands(
+    mut port: serialport::TTYPort,
+    commands: mpsc::Sender<HostCommand>,
+    status: Arc<AtomicU8>,
+    abort: Arc<AtomicBool>,
+) -> anyhow::Result<()> {
+    let mut replies = port.try_clone_native()?;
+    let mut parser = CommandParser::default();
+    let mut buffer = [0; 64];
+    loop {
+        let length = match port.read(&mut buffer) {
+            Ok(length) => length,
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e).context("reading from the pseudo terminal"),
+        };
+        for command in buffer[..length].iter().filter_map(|b| parser.push(*b)) {
+            trace!("WinKeyer host command {command:?}");
+            match command {
+                HostCommand::HostOpen => {
+                    info!("WinKeyer host mode opened by the logger");
+                    replies.write_all(&[VERSION])?;
+                }
+                HostCommand::Echo(byte) => replies.write_all(&[byte])?,
+                HostCommand::RequestStatus => {
+                    replies.write_all(&[status.load(Ordering::SeqCst)])?
+                }
+                command => {
+                    if command == HostCommand::Clear {
+                        abort.store(true, Ordering::SeqCst);
+                    }
+                    if commands.send(command).is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Creates the pseudo terminal (symlinked at `link`, if given) and
+/// sends whatever the host puts in the keyer's buffer to `output`.
+#[cfg(unix)]
+pub fn run(
+    link: Option<&str>,
+    player: &MorsePlayer,
+    mut output: CwOutput,
+    dot_duration: u32,
+    tone_freq: f32,
+) -> anyhow::Result<()> {
+    // Keep the slave end open, so the terminal survives the logger
+    // closing and reopening it:
+    let (master, slave) = serialport::TTYPort::pair().context("creating a pseudo terminal")?;
+    let slave_name = serialport::SerialPort::name(&slave).context("naming the pseudo terminal")?;
+    if let Some(link) = link {
+        let path = std::path::Path::new(link);
+        if path.is_symlink() {
+            std::fs::remove_file(path)?;
+        }
+        std::os::unix::fs::symlink(&slave_name, path)
+            .with_context(|| format!("linking {link} to {slave_name}"))?;
+    }
+    info!(
+        "WinKeyer emulator ready, connect your logger to {} - Press Ctrl-C to stop.",
+        link.unwrap_or(&slave_name)
+    );
+
+    let mut writer = master.try_clone_native()?;
+    let status = Arc::new(AtomicU8::new(STATUS_TAG));
+    let abort = Arc::new(AtomicBool::new(false));
+    let (sender, commands) = mpsc::channel();
+    let reader = {
+        let status = status.clone();
+        let abort = abort.clone();
+        std::thread::spawn(move || read_commands(master, sender, status, abort))
+    };
+
+    let mut buffer: VecDeque<Buffered> = VecDeque::new();
+    let mut speed = dot_duration;
+    let mut buffered_speed: Option<u32> = None;
+    let mut tone = tone_freq;
+    let mut mode = MODE_SERIAL_ECHO;
+    let mut xoff = false;
+    let mut transmitting: Option<PttHold> = None;
+    let mut ptt: Option<PttHold> = None;
+    loop {
+        // Report the status whenever it changes, like the real keyer:
+        xoff = match buffer.len() {
+            length if length >= XOFF_LEVEL => true,
+            length if length <= XON_LEVEL => false,
+            _ => xoff,
+        };
+        let busy = !buffer.is_empty();
+        if !busy {
+            transmitting = None;
+        }
+        let new_status =
+            STATUS_TAG | if busy { STATUS_BUSY } else { 0 } | if xoff { STATUS_XOFF } else { 0 };
+        if status.swap(new_status, Ordering::SeqCst) != new_status {
+            writer.write_all(&[new_status])?;
+        }
+
+        // Wait for commands while idle, otherwise only take what has arrived:
+        let command = if buffer.is_empty() {
+            match commands.recv() {
+                Ok(command) => Some(command),
+                Err(_) => break,
+            }
+        } else {
+            commands.try_recv().ok()
+        };
+        if let Some(command) = command {
+            match command {
+                HostCommand::Text(byte) => buffer.push_back(Buffered::Character(byte)),
+                HostCommand::BufferedSpeed(wpm) => buffer.push_back(Buffered::Speed(wpm)),
+                HostCommand::CancelBufferedSpeed => buffer.push_back(Buffered::CancelSpeed),
+                HostCommand::Clear => {
+                    buffer.clear();
+                    buffered_speed = None;
+                    abort.store(false, Ordering::SeqCst);
+                }
+                HostCommand::Speed(wpm) if wpm > 0 => speed = wpm_to_dot_length(wpm.into()),
+                HostCommand::Sidetone(divider) => {
+                    tone = 4000.0 / (divider & 0x0F).max(1) as f32;
+                }
+                HostCommand::Mode(value) => mode = value,
+                HostCommand::Ptt(true) => match player.transmit() {
+                    Ok(hold) => ptt = hold,
+                    // Keep serving, the next command may get through:
+                    Err(e) => error!("{e:#}"),
+                },
+                HostCommand::Ptt(false) => ptt = None,
+                HostCommand::KeyImmediate(down) => match &mut output {
+                    CwOutput::Key(key) => key.set(down)?,
+                    CwOutput::Sound => debug!("Ignoring key immediate on sound output"),
+                },
+                HostCommand::HostClose => {
+                    info!("WinKeyer host mode closed by the logger");
+                    buffer.clear();
+                    ptt = None;
+                }
+                command => debug!("Ignoring WinKeyer command {command:?}"),
+            }
+            continue;
+        }
+
+        let dot = buffered_speed.unwrap_or(speed);
+        match buffer.pop_front() {
+            Some(Buffered::Character(byte)) => {
+                if byte == b' ' {
+                    // The character gap was already sent, make it a word gap:
+                    player.send_gap(dot * 4, &mut output);
+                } else {
+                    if transmitting.is_none() {
+                        transmitting = player.transmit()?;
+                    }
+                    let character = (byte as char).to_string();
+                    player.send_abortable(&character, dot, tone, &mut output, &abort);
+                    player.send_gap(dot * 2, &mut output);
+                }
+                if mode & MODE_SERIAL_ECHO != 0 {
+                    writer.write_all(&[byte])?;
+                }
+            }
+            Some(Buffered::Speed(wpm)) if wpm > 0 => {
+                buffered_speed = Some(wpm_to_dot_length(wpm.into()));
+            }
+            Some(Buffered::Speed(_)) | Some(Buffered::CancelSpeed) => buffered_speed = None,
+            None => {}
+        }
+    }
+    drop((ptt, slave));
+    reader
+        .join()
+        .map_err(|_| anyhow::anyhow!("WinKeyer reader thread panicked"))?
+}
+
+#[cfg(not(unix))]
+pub fn run(
+    _link: Option<&str>,
+    _player: &MorsePlayer,
+    _output: CwOutput,
+    _dot_duration: u32,
+    _tone_freq: f32,
+) -> anyhow::Result<()> {
+    anyhow::bail!("The WinKeyer emulator needs pseudo terminals, which this platform lacks.")
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::*;
+    use serialport::TTYPort;
+
+    #[test]
+    fn test_read_commands_replies_and_forwards() {
+        let (master, mut host) = TTYPort::pair().unwrap();
+        let status = Arc::new(AtomicU8::new(STATUS_TAG | STATUS_BUSY));
+        let abort = Arc::new(AtomicBool::new(false));
+        let (sender, commands) = mpsc::channel();
+        {
+            let abort = abort.clone();
+            std::thread::spawn(move || read_commands(master, sender, status, abort));
+        }
+
+        host.write_all(&[ADMIN, ADMIN_HOST_OPEN, REQUEST_STATUS, SPEED, 30])
+            .unwrap();
+        host.write_all(b"TU").unwrap();
+        host.write_all(&[CLEAR_BUFFER]).unwrap();
+
+        let mut replies = [0; 2];
+        host.read_exact(&mut replies).unwrap();
+        assert_eq!(replies, [VERSION, STATUS_TAG | STATUS_BUSY]);
+        let forwarded: Vec<HostCommand> = (0..4).map(|_| commands.recv().unwrap()).collect();
+        assert_eq!(
+            forwarded,
+            vec![
+                HostCommand::Speed(30),
+                HostCommand::Text(b'T'),
+                HostCommand::Text(b'U'),
+                HostCommand::Clear,
+            ]
+        );
+        assert!(abort.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,112 @@
+//! End-to-end tests of the Linux input device backend, on virtual
+//! devices created through uinput. They need write access to
+//! /dev/uinput (root, or the `input` group), so they are ignored by
+//! default: run them with `cargo test --test evdev_uinput -- --ignored`.
+#![cfg(all(target_os = "linux", feature = "evdev"))]
+use evdev::uinput::VirtualDevice;
+use evdev::{AttributeSet, KeyCode, KeyEvent};
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::Duration;
+
+/// Creates a virtual device with the keys
+fn virtual_device(keys: &[KeyCode]) -> (VirtualDevice, PathBuf) {
+    let keys: AttributeSet<KeyCode> = keys.iter().copied().collect();
+    let created = VirtualDevice::builder()
+        .and_then(|builder| builder.name("code-smore test key").with_keys(&keys))
+        .and_then(|builder| builder.build());
+    let mut device = created.expect("Cannot create a uinput device");
+    let path = device
+        .enumerate_dev_nodes_blocking()
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    (device, path)
+}
+
+fn press(device: &mut VirtualDevice, key: KeyCode, duration: Duration) {
+    device.emit(&[*KeyEvent::new(key, 1)]).unwrap();
+    sleep(duration);
+    device.emit(&[*KeyEvent::new(key, 0)]).unwrap();
+}
+
+fn code_smore(args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_code-smore"))
+        .args(["--text", "--dot", "30", "receive"])
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+fn output(mut child: Child) -> String {
+    // Wait for the message to be finished by the pause:
+    sleep(Duration::from_millis(30 * 6 * 7 + 500));
+    let _ = child.kill();
+    let _ = child.wait();
+    let mut output = String::new();
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut output)
+        .unwrap();
+    output
+}
+
+#[test]
+#[ignore = "needs write access to /dev/uinput"]
+fn test_receive_decodes_input_straight_key() {
+    let (mut device, path) = virtual_device(&[KeyCode::KEY_SPACE]);
+    let path = path.to_str().unwrap();
+    let child = code_smore(&["--input-device", path, "--input-key", "KEY_SPACE"]);
+    sleep(Duration::from_millis(500));
+
+    // Key "SOS":
+    let dot = Duration::from_millis(30);
+    for (i, character) in ["...", "---", "..."].iter().enumerate() {
+        if i > 0 {
+            sleep(dot * 2); // Completes the character gap after the element gap
+        }
+        for element in character.chars() {
+            press(
+                &mut device,
+                KeyCode::KEY_SPACE,
+                if element == '.' { dot } else { dot * 3 },
+            );
+            sleep(dot);
+        }
+    }
+    let output = output(child);
+    assert!(output.contains("SOS"), "decoded: {output:?}");
+}
+
+#[test]
+#[ignore = "needs write access to /dev/uinput"]
+fn test_receive_decodes_input_paddles() {
+    let keys = [KeyCode::KEY_LEFTCTRL, KeyCode::KEY_RIGHTCTRL];
+    let (mut device, path) = virtual_device(&keys);
+    // The Ctrl keys are the paddles by default:
+    let child = code_smore(&["--input-device", path.to_str().unwrap()]);
+    sleep(Duration::from_millis(500));
+
+    // Hold the dah paddle for three dahs (4 dots each, with the gap),
+    // then the dit paddle for two dits (2 dots each): "OI"
+    press(
+        &mut device,
+        KeyCode::KEY_RIGHTCTRL,
+        Duration::from_millis(300),
+    );
+    sleep(Duration::from_millis(150));
+    press(
+        &mut device,
+        KeyCode::KEY_LEFTCTRL,
+        Duration::from_millis(75),
+    );
+    let output = output(child);
+    assert!(output.contains("OI"), "decoded: {output:?}");
+}
@@ -0,0 +1,242 @@
+//! End-to-end tests of the GPIO character device backend, on lines
+//! simulated by the kernel's gpio-sim module. They need root and the
+//! module loaded (`modprobe gpio-sim`), so they are ignored by default:
+//! run them with `cargo test --test gpio_sim -- --ignored`.
+#![cfg(all(target_os = "linux", feature = "gpio"))]
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const CONFIGFS: &str = "/sys/kernel/config/gpio-sim";
+
+/// A simulated GPIO chip, removed when dropped
+struct SimChip {
+    config: PathBuf,
+    chip_name: String, // e.g. gpiochip3
+    sysfs: PathBuf,    // Holds the sim_gpioN/{value,pull} attributes
+}
+
+impl SimChip {
+    /// Creates a chip with 8 lines.
+    fn new(name: &str) -> Self {
+        assert!(
+            Path::new(CONFIGFS).is_dir(),
+            "gpio-sim is not available (modprobe gpio-sim, as root)"
+        );
+        let config = Path::new(CONFIGFS).join(format!("{name}-{}", std::process::id()));
+        let bank = config.join("bank0");
+        let created = fs::create_dir(&config)
+            .and_then(|_| fs::create_dir(&bank))
+            .and_then(|_| fs::write(bank.join("num_lines"), "8"))
+            .and_then(|_| fs::write(config.join("live"), "1"));
+        if let Err(e) = created {
+            let _ = fs::remove_dir(&bank);
+            let _ = fs::remove_dir(&config);
+            panic!("Cannot create a gpio-sim chip: {e}");
+        }
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap().trim().to_string();
+        let chip_name = read(bank.join("chip_name"));
+        let sysfs = Path::new("/sys/devices/platform")
+            .join(read(config.join("dev_name")))
+            .join(&chip_name);
+        Self {
+            config,
+            chip_name,
+            sysfs,
+        }
+    }
+
+    fn value(&self, line: u8) -> bool {
+        fs::read_to_string(self.sysfs.join(format!("sim_gpio{line}/value")))
+            .unwrap()
+            .trim()
+            == "1"
+    }
+
+    /// Drives an input line from the outside
+    fn pull(&self, line: u8, high: bool) {
+        let pull = if high { "pull-up" } else { "pull-down" };
+        fs::write(self.sysfs.join(format!("sim_gpio{line}/pull")), pull).unwrap();
+    }
+}
+
+impl Drop for SimChip {
+    fn drop(&mut self) {
+        let _ = fs::write(self.config.join("live"), "0");
+        let _ = fs::remove_dir(self.config.join("bank0"));
+        let _ = fs::remove_dir(&self.config);
+    }
+}
+
+fn code_smore(chip: &SimChip, args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_code-smore"))
+        .args(["--gpio-chip", &chip.chip_name])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+#[test]
+#[ignore = "needs root and the gpio-sim module"]
+fn test_send_keys_gpio_chip_line() {
+    let chip = SimChip::new("code-smore-send");
+    let mut child = code_smore(&chip, &["--gpio", "0", "--dot", "20", "send"]);
+    child.stdin.take().unwrap().write_all(b"ET\n").unwrap();
+
+    // Sample the line, timing how long it stays high each time:
+    let mut elements = Vec::new();
+    let mut high_since: Option<Instant> = None;
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(3) {
+        match (chip.value(0), high_since) {
+            (true, None) => high_since = Some(Instant::now()),
+            (false, Some(since)) => {
+                elements.push(since.elapsed().as_millis());
+                high_since = None;
+            }
+            _ => {}
+        }
+        if elements.len() == 2 {
+            break;
+        }
+        sleep(Duration::from_millis(1));
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+
+    assert_eq!(elements.len(), 2, "keyed elements: {elements:?}");
+    assert!((15..=35).contains(&elements[0]), "dot: {} ms", elements[0]);
+    assert!((50..=80).contains(&elements[1]), "dash: {} ms", elements[1]);
+    assert!(!chip.value(0), "the key was left down");
+}
+
+#[test]
+#[ignore = "needs root and the gpio-sim module"]
+fn test_receive_decodes_gpio_chip_line() {
+    let chip = SimChip::new("code-smore-receive");
+    // The key is active low, start with it up:
+    chip.pull(1, true);
+    let mut child = code_smore(&chip, &["--gpio", "1", "--dot", "30", "receive"]);
+    sleep(Duration::from_millis(500));
+
+    // Key "SOS" on the line:
+    let dot = Duration::from_millis(30);
+    for (i, character) in ["...", "---", "..."].iter().enumerate() {
+        if i > 0 {
+            sleep(dot * 2); // Completes the character gap after the element gap
+        }
+        for element in character.chars() {
+            chip.pull(1, false);
+            sleep(if element == '.' { dot } else { dot * 3 });
+            chip.pull(1, true);
+            sleep(dot);
+        }
+    }
+    // Wait for the message to be finished by the pause:
+    sleep(dot * 6 * 7 + Duration::from_millis(500));
+    let _ = child.kill();
+    let _ = child.wait();
+
+    let mut output = String::new();
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut output)
+        .unwrap();
+    assert!(output.contains("SOS"), "decoded: {output:?}");
+}
+
+#[test]
+#[ignore = "needs root and the gpio-sim module"]
+fn test_send_keys_gpio_ptt_around_the_key_line() {
+    let chip = SimChip::new("code-smore-ptt");
+    let args = ["--gpio", "0", "--gpio-ptt", "2", "--ptt-lead", "50"];
+    let mut child = code_smore(
+        &chip,
+        &[&args[..], &["--ptt-tail", "100", "--dot", "20", "send"]].concat(),
+    );
+    child.stdin.take().unwrap().write_all(b"E\n").unwrap();
+
+    // Sample both lines, noting when each rises and falls:
+    let (mut key, mut ptt) = ((None, None), (None, None));
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(3) && ptt.1.is_none() {
+        for (line, (rose, fell)) in [(0, &mut key), (2, &mut ptt)] {
+            match (chip.value(line), *rose, *fell) {
+                (true, None, _) => *rose = Some(start.elapsed()),
+                (false, Some(_), None) => *fell = Some(start.elapsed()),
+                _ => {}
+            }
+        }
+        sleep(Duration::from_millis(1));
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+
+    let (Some(key_down), Some(key_up)) = key else {
+        panic!("the key line was not keyed: {key:?}");
+    };
+    let (Some(ptt_down), Some(ptt_up)) = ptt else {
+        panic!("PTT was not keyed and released: {ptt:?}");
+    };
+    let lead = key_down.saturating_sub(ptt_down).as_millis();
+    let tail = ptt_up.saturating_sub(key_up).as_millis();
+    assert!((40..=80).contains(&lead), "PTT lead: {lead} ms");
+    assert!((90..=150).contains(&tail), "PTT tail: {tail} ms");
+}
+
+/// Runs without gpio-sim: the pins are checked before any is opened.
+#[test]
+fn test_gpio_ptt_rejects_the_keying_pin() {
+    let output = Command::new(env!("CARGO_BIN_EXE_code-smore"))
+        .args(["--gpio", "3", "--gpio-ptt", "3", "send"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--gpio-ptt"), "stderr: {stderr}");
+}
+
+#[test]
+#[ignore = "needs root and the gpio-sim module"]
+fn test_buzzer_toggles_gpio_chip_line_while_keyed() {
+    let chip = SimChip::new("code-smore-buzzer");
+    // A 50 Hz tone toggles the line every 10ms, slow enough to sample:
+    let args = ["--gpio-buzzer", "4", "--tone", "50", "--dot", "100", "send"];
+    let mut child = code_smore(&chip, &args);
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"E\n").unwrap();
+
+    // Count the toggles until the line has been still for a while:
+    let mut toggles = 0;
+    let mut level = chip.value(4);
+    let mut changed = Instant::now();
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(3) {
+        if chip.value(4) != level {
+            level = !level;
+            toggles += 1;
+            changed = Instant::now();
+        }
+        if toggles > 0 && changed.elapsed() > Duration::from_millis(300) {
+            break;
+        }
+        sleep(Duration::from_millis(1));
+    }
+    let idle = chip.value(4);
+    drop(stdin);
+    let _ = child.kill();
+    let _ = child.wait();
+
+    // The 100ms dot sounds about 10 half periods:
+    assert!((6..=14).contains(&toggles), "toggles: {toggles}");
+    assert!(!idle, "the buzzer was left high");
+}